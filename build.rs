@@ -31,7 +31,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         let tailwind_binary = out_dir.join("tailwind");
         fs::create_dir_all(&out_dir)?;
 
-        download_tailwind(&download_url, &tailwind_binary)?;
+        download_tailwind(download_url, &tailwind_binary)?;
         println!("cargo:rustc-env=TAILWIND_BIN={}", tailwind_binary.display());
         run_tailwind(&tailwind_binary)?;
     }
@@ -49,7 +49,7 @@ fn run_tailwind(bin: &PathBuf) -> Result<(), Box<dyn Error>> {
     let outputstr = output.to_str().unwrap();
     let args = vec!["-i", inputstr, "-o", outputstr, "--minify"];
 
-    let run = Command::new(&bin).args(args).status()?;
+    let run = Command::new(bin).args(args).status()?;
     match run.success() {
         true => println!("Tailwind CSS build complete."),
         false => println!("Tailwind CSS build failed."),
@@ -1,15 +1,53 @@
+use std::{collections::HashMap, convert::Infallible, error::Error, net::SocketAddr, sync::Arc};
+
 use axum::{
     Form, Json,
+    extract::{ConnectInfo, Path, State},
     http::{HeaderMap, StatusCode, header},
-    response::{IntoResponse, Redirect, Response},
+    response::{
+        IntoResponse, Redirect, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures_util::{Stream, StreamExt, stream};
+use maud::Markup;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
 use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
 
 use crate::{
-    database::open_db,
+    audit::{self, Action, snapshot},
+    cash::{CashCount, CashError},
+    config::{self, Config, parse_date},
+    containers::Container,
+    contributions::{Contribution, ContributionEntry},
+    error::{AppError, SERVER_ERROR},
+    flash::{Flash, FlashRedirect},
+    html::{
+        controls::{
+            controls_totp_login,
+            settings::{new_token_page, recovery_codes_page},
+        },
+        stats::{render_tiles, stats_visible},
+    },
+    money::{Money, MoneyError},
+    scheduler,
+    state::AppState,
+    stats::Summary,
     users::{
-        User,
-        auth::{COOKIE_CLEAR, COOKIE_NAME},
+        User, UserStructError,
+        auth::{
+            AuthError, COOKIE_CLEAR, check_credentials, cookie_token, log_failed_login,
+            session_cookie,
+        },
+        csrf::{CsrfForm, CsrfToken, NoFields},
+        roles::{Permission, Role},
+        sessions::{Session, SessionStructError},
+        throttle::{self, Subject},
+        tokens::{ApiToken, Scope},
+        totp::{self, SecondFactor, TotpError},
     },
 };
 
@@ -23,11 +61,65 @@ pub async fn css() -> Response {
     ([(header::CONTENT_TYPE, "text/css")], CSS).into_response()
 }
 
-pub async fn me(headers: HeaderMap) -> Result<Response, (StatusCode, String)> {
-    let conn =
-        open_db().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "couldnt open db".into()))?;
-    let user = User::authenticate(&headers, &conn)
-        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+/// Whether the request may see the stats, see [`stats_visible`].
+async fn may_see_stats(state: &AppState, headers: HeaderMap) -> Result<bool, AppError> {
+    state
+        .with_db(move |conn| {
+            Config::load(conn)
+                .map(|config| stats_visible(&config, &headers, conn))
+                .map_err(AppError::read("config"))
+        })
+        .await?
+}
+
+/// Server-sent stream of dashboard tile updates for the public stats page.
+/// The current tiles are sent right away, so reconnecting clients catch up too.
+/// Every update is checked against the viewer again, ending the stream once the stats
+/// are made private or the viewer's session ends.
+pub async fn stats_live(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    if !may_see_stats(&state, headers.clone()).await? {
+        return Err(AppError::Unauthenticated);
+    }
+    let rx = state.subscribe_stats();
+    let current = state
+        .with_db(|conn| render_tiles(conn).ok())
+        .await
+        .ok()
+        .flatten()
+        .map(Arc::<str>::from);
+    let updates = stream::unfold(
+        (rx, state, headers),
+        |(mut rx, state, headers)| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(tiles) => {
+                        if !may_see_stats(&state, headers.clone())
+                            .await
+                            .unwrap_or(false)
+                        {
+                            return None;
+                        }
+                        return Some((tiles, (rx, state, headers)));
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+    let events = stream::iter(current)
+        .chain(updates)
+        .map(|tiles| Ok(Event::default().event("stats").data(tiles)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+pub async fn me(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, AppError> {
+    let user = state
+        .with_db(move |conn| User::authenticate(&headers, conn))
+        .await??;
     if let Some(u) = user {
         Ok(Json(u).into_response())
     } else {
@@ -35,7 +127,37 @@ pub async fn me(headers: HeaderMap) -> Result<Response, (StatusCode, String)> {
     }
 }
 
-pub async fn logout_redir() -> Response {
+pub async fn logout_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    let logged_out = state
+        .with_db(move |conn| -> Result<(), Box<dyn Error + Send + Sync>> {
+            let Some(token) = cookie_token(&headers) else {
+                return Ok(());
+            };
+            let session = Session::get_by_token(token, conn)?;
+            if session.is_expired_or_revoked() {
+                return Ok(());
+            }
+            Session::revoke(session.id(), conn)?;
+            audit::record(
+                conn,
+                Some(session.user_id()),
+                Action::Logout,
+                Some(session.user_id()),
+                None,
+                None,
+            )?;
+            Ok(())
+        })
+        .await;
+    match logged_out {
+        Ok(Err(e)) => eprintln!("failed to end session on logout: {e}"),
+        Err(e) => eprintln!("failed to end session on logout: {e}"),
+        Ok(Ok(())) => (),
+    }
     ([(header::SET_COOKIE, COOKIE_CLEAR)], Redirect::to("/panel")).into_response()
 }
 
@@ -45,71 +167,1324 @@ pub struct LoginForm {
     password: String,
 }
 
-pub async fn login_redir(Form(form): Form<LoginForm>) -> Response {
-    use crate::users::pwd::verify_password;
-    use crate::users::sessions::Session;
+/// Logging in isn't checked for forgery: there's no session to forge a request with
+/// yet, and the second step is bound to the login challenge.
+pub async fn login_redir(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(str::to_owned);
+    redirect_with_db(&state, "/panel", move |conn| {
+        login(conn, form, user_agent, addr.ip().to_string())
+    })
+    .await
+}
 
-    let conn = match open_db() {
-        Ok(c) => c,
-        Err(_) => {
-            return Redirect::to("/panel?error=Błąd serwera. Skontaktuj się z webmasterem.")
+fn login(conn: &Connection, form: LoginForm, user_agent: Option<String>, ip: String) -> Response {
+    let user_id = match check_credentials(&form.username, &form.password, Some(&ip), conn) {
+        Ok(id) => id,
+        Err(AuthError::UserError(_)) => {
+            return Flash::error("Błąd serwera. Spróbuj ponownie później.")
+                .redirect("/panel")
                 .into_response();
         }
+        Err(e) => return Flash::error(e.msg()).redirect("/panel").into_response(),
     };
 
-    let user_result = conn
-        .prepare("SELECT id, passhash FROM users WHERE handle = ?1")
-        .and_then(|mut stmt| {
-            stmt.query_row([&form.username], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-            })
-        });
+    // accounts with a second factor get asked for it before they get a session
+    match totp::is_enabled(&user_id, conn).and_then(|enabled| {
+        enabled
+            .then(|| totp::create_challenge(&user_id, conn))
+            .transpose()
+    }) {
+        Ok(None) => start_session(conn, &user_id, user_agent, ip, None),
+        Ok(Some(challenge)) => {
+            controls_totp_login(&config::competition_name(conn), &challenge, None).into_response()
+        }
+        Err(e) => Flash::error(e.msg()).redirect("/panel").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TotpLoginForm {
+    challenge: String,
+    code: String,
+}
+
+/// Second step of logging in, for accounts with two-factor authentication.
+pub async fn login_totp_redir(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<TotpLoginForm>,
+) -> Response {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(str::to_owned);
+    redirect_with_db(&state, "/panel", move |conn| {
+        login_totp(conn, form, user_agent, addr.ip().to_string())
+    })
+    .await
+}
+
+fn login_totp(
+    conn: &Connection,
+    form: TotpLoginForm,
+    user_agent: Option<String>,
+    ip: String,
+) -> Response {
+    let fail = |msg: &str| Flash::error(msg).redirect("/panel").into_response();
+    let user = match totp::challenge_user(&form.challenge, conn)
+        .map_err(AuthError::from)
+        .and_then(|id| Ok(User::get_by_uuid(&id, conn)?))
+    {
+        Ok(u) => u,
+        Err(e) => return fail(e.msg()),
+    };
+    let subjects = [Subject::Account(&user.handle), Subject::Ip(&ip)];
+    match throttle::locked_until(&subjects, conn) {
+        Ok(None) => {}
+        Ok(Some(until)) => {
+            log_failed_login(conn, Some(&user.id), &user.handle, Some(&ip), "locked");
+            _ = totp::delete_challenge(&form.challenge, conn);
+            return fail(AuthError::TooManyAttempts(until).msg());
+        }
+        Err(_) => return fail(SERVER_ERROR),
+    }
 
-    let (id_str, passhash) = match user_result {
-        Ok(result) => result,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            return Redirect::to("/panel?error=Nieprawidłowy login lub hasło.").into_response();
+    match totp::check(&user.id, &form.code, conn) {
+        Ok(factor) => {
+            if let Err(e) = totp::delete_challenge(&form.challenge, conn)
+                .and_then(|_| Ok(throttle::clear(&subjects[0], conn)?))
+            {
+                eprintln!("failed to finish login challenge: {e}");
+            }
+            start_session(conn, &user.id, user_agent, ip, Some(factor))
+        }
+        Err(TotpError::InvalidCode) => {
+            if let Err(e) = throttle::record_failure(&subjects, conn) {
+                eprintln!("failed to count failed login: {e}");
+            }
+            log_failed_login(
+                conn,
+                Some(&user.id),
+                &user.handle,
+                Some(&ip),
+                "second_factor",
+            );
+            controls_totp_login(
+                &config::competition_name(conn),
+                &form.challenge,
+                Some(TotpError::InvalidCode.msg()),
+            )
+            .into_response()
         }
+        Err(e) => fail(e.msg()),
+    }
+}
+
+/// Opens a session for a user who has proven who they are, logging them in.
+fn start_session(
+    conn: &Connection,
+    user_id: &Uuid,
+    user_agent: Option<String>,
+    ip: String,
+    second_factor: Option<SecondFactor>,
+) -> Response {
+    let (token, expiry) = match Session::create(user_id, user_agent.as_deref(), Some(&ip), conn) {
+        Ok(t) => t,
         Err(_) => {
-            return Redirect::to("/panel?error=Błąd serwera. Skontaktuj się z webmasterem.")
+            return Flash::error("Nie udało się utworzyć sesji.")
+                .redirect("/panel")
                 .into_response();
         }
     };
+    let details = second_factor.map(|f| json!({ "second_factor": f.as_str() }));
+    if let Err(e) = audit::record(
+        conn,
+        Some(user_id),
+        Action::Login,
+        Some(user_id),
+        None,
+        details,
+    ) {
+        eprintln!("failed to log login: {e}");
+    }
 
-    let password_valid = match verify_password(&form.password, &passhash) {
-        Ok(valid) => valid,
-        Err(_) => {
-            return Redirect::to("/panel?error=Błąd weryfikacji hasła.").into_response();
-        }
+    (
+        [(header::SET_COOKIE, session_cookie(&token, expiry))],
+        Redirect::to("/panel"),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct NewContributionForm {
+    contrbank: String,
+    contramt: String,
+    contrnote: Option<String>,
+    /// Generated anew for every rendered form; missing from forms rendered before it was.
+    contrkey: Option<String>,
+}
+
+pub async fn new_contribution_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<NewContributionForm>,
+) -> Response {
+    let publisher = state.clone();
+    redirect_with_db(&state, "/panel", move |conn| {
+        new_contribution(conn, &publisher, &headers, form)
+    })
+    .await
+}
+
+fn new_contribution(
+    conn: &Connection,
+    state: &AppState,
+    headers: &HeaderMap,
+    form: NewContributionForm,
+) -> Response {
+    let fail = |msg: &str| Flash::error(msg).redirect("/panel").into_response();
+    let user = match User::authenticate(headers, conn) {
+        Ok(Some(u)) => u,
+        Ok(None) => return Redirect::to("/panel").into_response(),
+        Err(e) => return fail(e.msg()),
+    };
+    if let Err(e) = user.require(Permission::RecordContributions) {
+        return fail(e.msg());
+    }
+
+    let container = match uuid::Uuid::parse_str(&form.contrbank) {
+        Ok(id) => id,
+        Err(_) => return fail("Wybierz poprawny pojemnik."),
+    };
+    let amount = match form.contramt.parse::<Money>() {
+        Ok(a) if a > Money::ZERO => a,
+        Ok(_) => return fail("Nieprawidłowa wielkość datku."),
+        Err(e) => return fail(e.msg()),
+    };
+    let notes = form
+        .contrnote
+        .map(|n| n.trim().to_owned())
+        .filter(|n| !n.is_empty());
+    let key = match form.contrkey.as_deref().map(Uuid::parse_str).transpose() {
+        Ok(key) => key,
+        Err(_) => return fail("Nieprawidłowy formularz. Odśwież stronę."),
     };
 
-    if !password_valid {
-        return Redirect::to("/panel?error=Nieprawidłowy login lub hasło.").into_response();
+    match record_contribution(conn, &user, &container, amount, notes, key.as_ref(), None) {
+        Ok((c, new)) => {
+            if new {
+                state.publish_stats(conn);
+            }
+            Flash::success(format!("Odnotowano datek w wysokości {}.", c.amount))
+                .redirect("/panel")
+                .into_response()
+        }
+        Err(msg) => fail(msg),
     }
+}
 
-    let user_id = match uuid::Uuid::parse_str(&id_str) {
+#[derive(Deserialize)]
+pub struct CashCountForm {
+    contrbank: String,
+    contrnote: Option<String>,
+    contrkey: Option<String>,
+    /// Total expected in the container, checked against the count if given.
+    contrdeclared: Option<String>,
+    /// Quantities of coins and notes, see [`CashCount::from_fields`].
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+pub async fn cash_count_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<CashCountForm>,
+) -> Response {
+    let publisher = state.clone();
+    redirect_with_db(&state, "/panel/liczenie", move |conn| {
+        cash_count(conn, &publisher, &headers, form)
+    })
+    .await
+}
+
+/// Records a contribution counted out coin by coin, for a total the server works out.
+fn cash_count(
+    conn: &Connection,
+    state: &AppState,
+    headers: &HeaderMap,
+    form: CashCountForm,
+) -> Response {
+    let fail = |msg: &str| {
+        Flash::error(msg)
+            .redirect("/panel/liczenie")
+            .into_response()
+    };
+    let user = match User::authenticate(headers, conn) {
+        Ok(Some(u)) => u,
+        Ok(None) => return Redirect::to("/panel").into_response(),
+        Err(e) => return fail(e.msg()),
+    };
+    if let Err(e) = user.require(Permission::RecordContributions) {
+        return fail(e.msg());
+    }
+
+    let container = match uuid::Uuid::parse_str(&form.contrbank) {
         Ok(id) => id,
-        Err(_) => {
-            return Redirect::to("/panel?error=Błąd serwera. Spróbuj ponownie później.")
-                .into_response();
+        Err(_) => return fail("Wybierz poprawny pojemnik."),
+    };
+    let count =
+        match CashCount::from_fields(form.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))) {
+            Ok(count) => count,
+            Err(e) => return fail(e.msg()),
+        };
+    let declared = match form.contrdeclared.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(d) => match d.parse::<Money>() {
+            Ok(d) => Some(d),
+            Err(e) => return fail(e.msg()),
+        },
+    };
+    let amount = match declared.map_or_else(|| count.total(), |d| count.check_declared(d)) {
+        Ok(a) => a,
+        Err(CashError::Mismatch) => {
+            return fail(&format!(
+                "Przeliczono {}, a zadeklarowano {}. Przelicz jeszcze raz.",
+                count.total().unwrap_or(Money::ZERO),
+                declared.unwrap_or(Money::ZERO)
+            ));
         }
+        Err(e) => return fail(e.msg()),
+    };
+    let notes = form
+        .contrnote
+        .map(|n| n.trim().to_owned())
+        .filter(|n| !n.is_empty());
+    let key = match form.contrkey.as_deref().map(Uuid::parse_str).transpose() {
+        Ok(key) => key,
+        Err(_) => return fail("Nieprawidłowy formularz. Odśwież stronę."),
     };
 
-    let token = match Session::create(&user_id, &conn) {
-        Ok(t) => t,
-        Err(_) => {
-            return Redirect::to("/panel?error=Nie udało się utworzyć sesji.").into_response();
+    match record_contribution(
+        conn,
+        &user,
+        &container,
+        amount,
+        notes,
+        key.as_ref(),
+        Some(count),
+    ) {
+        Ok((c, new)) => {
+            if new {
+                state.publish_stats(conn);
+            }
+            let pieces = c.cash_count.as_ref().map_or(0, CashCount::pieces);
+            Flash::success(format!(
+                "Odnotowano datek w wysokości {} ({pieces} szt. monet i banknotów).",
+                c.amount
+            ))
+            .redirect("/panel/liczenie")
+            .into_response()
         }
+        Err(msg) => fail(msg),
+    }
+}
+
+/// Records a contribution along with its audit log entry, in one transaction. If one
+/// was already recorded with the same idempotency key, it's returned instead, and the
+/// flag saying the contribution is new is false.
+fn record_contribution(
+    conn: &Connection,
+    user: &User,
+    container: &Uuid,
+    amount: Money,
+    notes: Option<String>,
+    idempotency_key: Option<&Uuid>,
+    cash_count: Option<CashCount>,
+) -> Result<(Contribution, bool), &'static str> {
+    // taking the write lock up front, so that a resubmission sent at the same time
+    // waits for this one and then finds its contribution
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+        .map_err(|_| SERVER_ERROR)?;
+    if let Some(key) = idempotency_key
+        && let Some(c) =
+            Contribution::get_by_idempotency_key(key, &user.id, &tx).map_err(|e| e.msg())?
+    {
+        return Ok((c, false));
+    }
+    let c = Contribution::create(
+        container,
+        amount,
+        notes,
+        &user.id,
+        idempotency_key,
+        cash_count,
+        &tx,
+    )
+    .map_err(|e| e.msg())?;
+    audit::record(
+        &tx,
+        Some(&user.id),
+        Action::ContributionCreated,
+        Some(&c.id),
+        None,
+        snapshot(&c),
+    )
+    .map_err(|e| e.msg())?;
+    tx.commit().map_err(|_| SERVER_ERROR)?;
+    Ok((c, true))
+}
+
+#[derive(Deserialize)]
+pub struct CorrectContributionForm {
+    contramt: String,
+    reason: String,
+}
+
+pub async fn correct_contribution_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<CorrectContributionForm>,
+) -> Response {
+    let back = format!("/panel/datki/{id}");
+    stats_redir(&state, headers, back, move |conn, user| {
+        let before = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        before.require_amend(user).map_err(|e| e.msg())?;
+        let amount = form.contramt.parse::<Money>().map_err(|e| e.msg())?;
+        Contribution::correct(&id, amount, &form.reason, &user.id, conn).map_err(|e| e.msg())?;
+        let after = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContributionCorrected,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Poprawiono datek.")
+    })
+    .await
+}
+
+/// Takes back the contribution just recorded, from the panel's new contribution form.
+pub async fn undo_contribution_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    stats_redir(&state, headers, "/panel", move |conn, user| {
+        user.require(Permission::RecordContributions)
+            .map_err(|e| e.msg())?;
+        let before = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        Contribution::undo(&id, &user.id, conn).map_err(|e| e.msg())?;
+        let after = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContributionUndone,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Cofnięto datek.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct VoidContributionForm {
+    reason: String,
+}
+
+pub async fn void_contribution_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<VoidContributionForm>,
+) -> Response {
+    let back = format!("/panel/datki/{id}");
+    stats_redir(&state, headers, back, move |conn, user| {
+        let before = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        before.require_amend(user).map_err(|e| e.msg())?;
+        Contribution::void(&id, &form.reason, &user.id, conn).map_err(|e| e.msg())?;
+        let after = ContributionEntry::get(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContributionVoided,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Unieważniono datek.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ApiContribution {
+    container: Uuid,
+    amount: Money, // in grosze
+    notes: Option<String>,
+    /// Lets a script retry a request safely: the same key gets the same contribution.
+    idempotency_key: Option<Uuid>,
+}
+
+/// Records a contribution from a script, for tokens with the `contributions:write` scope.
+pub async fn api_new_contribution(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<ApiContribution>,
+) -> Result<Response, AppError> {
+    let publisher = state.clone();
+    state
+        .with_db(move |conn| {
+            let user = User::authenticate(&headers, conn)?.ok_or(AppError::Unauthenticated)?;
+            user.require_scope(Scope::ContributionsWrite)
+                .and_then(|_| user.require(Permission::RecordContributions))?;
+            if body.amount == Money::ZERO {
+                return Err(AppError::Rejected("Nieprawidłowa wielkość datku."));
+            }
+            let notes = body
+                .notes
+                .map(|n| n.trim().to_owned())
+                .filter(|n| !n.is_empty());
+            let (c, new) = record_contribution(
+                conn,
+                &user,
+                &body.container,
+                body.amount,
+                notes,
+                body.idempotency_key.as_ref(),
+                None,
+            )
+            .map_err(AppError::Rejected)?;
+            if !new {
+                return Ok((StatusCode::OK, Json(c)).into_response());
+            }
+            publisher.publish_stats(conn);
+            Ok((StatusCode::CREATED, Json(c)).into_response())
+        })
+        .await?
+}
+
+/// Current standings as JSON, for tokens with the `stats:read` scope.
+pub async fn api_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    state
+        .with_db(move |conn| {
+            let user = User::authenticate(&headers, conn)?.ok_or(AppError::Unauthenticated)?;
+            user.require_scope(Scope::StatsRead)?;
+            let summary = Summary::load(conn).map_err(AppError::read("stats"))?;
+            Ok(Json(summary).into_response())
+        })
+        .await?
+}
+
+#[derive(Deserialize)]
+pub struct ContainerNameForm {
+    contname: String,
+}
+
+#[derive(Deserialize)]
+pub struct ContainerArchiveForm {
+    archived: bool,
+}
+
+/// Runs a form handler on the database thread pool, redirecting back to `back`
+/// with a server error if no connection could be had.
+async fn redirect_with_db(
+    state: &AppState,
+    back: &str,
+    handler: impl FnOnce(&Connection) -> Response + Send + 'static,
+) -> Response {
+    state.with_db(handler).await.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        Flash::error(SERVER_ERROR).redirect(back).into_response()
+    })
+}
+
+/// Shared plumbing for the panel management forms: authenticates the request,
+/// runs the change in a transaction and redirects back to `back` with a notice.
+async fn panel_redir(
+    state: &AppState,
+    headers: HeaderMap,
+    back: impl Into<String>,
+    change: impl FnOnce(&Connection, &User) -> Result<&'static str, &'static str> + Send + 'static,
+) -> Response {
+    changed_redir(state, headers, back.into(), None, change).await
+}
+
+/// Like [`panel_redir`], for changes to what the stats page shows, which live viewers
+/// are sent once the change is committed.
+async fn stats_redir(
+    state: &AppState,
+    headers: HeaderMap,
+    back: impl Into<String>,
+    change: impl FnOnce(&Connection, &User) -> Result<&'static str, &'static str> + Send + 'static,
+) -> Response {
+    changed_redir(state, headers, back.into(), Some(state.clone()), change).await
+}
+
+async fn changed_redir(
+    state: &AppState,
+    headers: HeaderMap,
+    back: String,
+    publisher: Option<AppState>,
+    change: impl FnOnce(&Connection, &User) -> Result<&'static str, &'static str> + Send + 'static,
+) -> Response {
+    redirect_with_db(state, &back.clone(), move |conn| {
+        match panel_change(conn, &headers, &back, change) {
+            Ok(msg) => {
+                if let Some(publisher) = publisher {
+                    publisher.publish_stats(conn);
+                }
+                Flash::success(msg).redirect(back).into_response()
+            }
+            Err(redirect) => redirect.into_response(),
+        }
+    })
+    .await
+}
+
+/// Like [`panel_redir`], but shows the page the change renders instead of redirecting,
+/// for results that can't be looked up again later, like secrets.
+async fn panel_page(
+    state: &AppState,
+    headers: HeaderMap,
+    back: &'static str,
+    change: impl FnOnce(&Connection, &User, &CsrfToken) -> Result<Markup, &'static str> + Send + 'static,
+) -> Response {
+    redirect_with_db(state, back, move |conn| {
+        let csrf = CsrfToken::from_headers(&headers);
+        match panel_change(conn, &headers, back, |conn, user| change(conn, user, &csrf)) {
+            Ok(page) => page.into_response(),
+            Err(redirect) => redirect.into_response(),
+        }
+    })
+    .await
+}
+
+/// Authenticates a panel form and runs its change in a transaction, or says where to
+/// send the user instead. The transaction takes the write lock up front, so nothing
+/// the change checks before writing can be changed by someone else in between.
+fn panel_change<T>(
+    conn: &Connection,
+    headers: &HeaderMap,
+    back: &str,
+    change: impl FnOnce(&Connection, &User) -> Result<T, &'static str>,
+) -> Result<T, FlashRedirect> {
+    let user = match User::authenticate(headers, conn) {
+        Ok(Some(u)) => u,
+        Ok(None) => return Err(FlashRedirect::to("/panel")),
+        Err(e) => return Err(Flash::error(e.msg()).redirect("/panel")),
     };
 
-    let secure = match cfg!(debug_assertions) {
-        false => "; Secure",
-        true => "",
+    Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+        .map_err(|_| SERVER_ERROR)
+        .and_then(|tx| {
+            let result = change(&tx, &user)?;
+            tx.commit().map_err(|_| SERVER_ERROR)?;
+            Ok(result)
+        })
+        .map_err(|msg| Flash::error(msg).redirect(back))
+}
+
+pub async fn new_container_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<ContainerNameForm>,
+) -> Response {
+    stats_redir(&state, headers, "/panel/pojemniki", move |conn, user| {
+        user.require(Permission::ManageContainers)
+            .map_err(|e| e.msg())?;
+        let c = Container::create(&form.contname, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContainerCreated,
+            Some(&c.id),
+            None,
+            snapshot(&c),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Utworzono pojemnik.")
+    })
+    .await
+}
+
+pub async fn rename_container_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<ContainerNameForm>,
+) -> Response {
+    stats_redir(&state, headers, "/panel/pojemniki", move |conn, user| {
+        user.require(Permission::ManageContainers)
+            .map_err(|e| e.msg())?;
+        let before = Container::get_by_id(&id, conn).map_err(|e| e.msg())?;
+        Container::rename(&id, &form.contname, conn).map_err(|e| e.msg())?;
+        let after = Container::get_by_id(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContainerRenamed,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zmieniono nazwę pojemnika.")
+    })
+    .await
+}
+
+pub async fn archive_container_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<ContainerArchiveForm>,
+) -> Response {
+    stats_redir(&state, headers, "/panel/pojemniki", move |conn, user| {
+        user.require(Permission::ManageContainers)
+            .map_err(|e| e.msg())?;
+        let before = Container::get_by_id(&id, conn).map_err(|e| e.msg())?;
+        Container::set_archived(&id, form.archived, conn).map_err(|e| e.msg())?;
+        let after = Container::get_by_id(&id, conn).map_err(|e| e.msg())?;
+        let action = match form.archived {
+            true => Action::ContainerArchived,
+            false => Action::ContainerUnarchived,
+        };
+        audit::record(
+            conn,
+            Some(&user.id),
+            action,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok(match form.archived {
+            true => "Zarchiwizowano pojemnik.",
+            false => "Przywrócono pojemnik z archiwum.",
+        })
+    })
+    .await
+}
+
+pub async fn delete_container_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    stats_redir(&state, headers, "/panel/pojemniki", move |conn, user| {
+        user.require(Permission::ManageContainers)
+            .map_err(|e| e.msg())?;
+        let before = Container::get_by_id(&id, conn).map_err(|e| e.msg())?;
+        Container::delete(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ContainerDeleted,
+            Some(&id),
+            snapshot(&before),
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Usunięto pojemnik.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordForm {
+    current: String,
+    new: String,
+    repeat: String,
+}
+
+pub async fn change_password_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<ChangePasswordForm>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        if !user
+            .check_password(&form.current, conn)
+            .map_err(|e| e.msg())?
+        {
+            return Err("Obecne hasło jest nieprawidłowe.");
+        }
+        if form.new != form.repeat {
+            return Err("Nowe hasła nie są takie same.");
+        }
+        User::set_password(&user.id, &form.new, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::UserPasswordChanged,
+            Some(&user.id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zmieniono hasło.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct NewUserForm {
+    handle: String,
+    password: String,
+    role: Role,
+}
+
+pub async fn new_user_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<NewUserForm>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        let created =
+            User::create(&form.handle, &form.password, form.role, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::UserCreated,
+            Some(&created.id),
+            None,
+            snapshot(&created),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Utworzono konto.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    password: String,
+}
+
+pub async fn reset_password_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<ResetPasswordForm>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        User::set_password(&id, &form.password, conn).map_err(|e| e.msg())?;
+        User::revoke_sessions(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::UserPasswordReset,
+            Some(&id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zresetowano hasło konta.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct RoleForm {
+    role: Role,
+}
+
+pub async fn set_role_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    CsrfForm(form): CsrfForm<RoleForm>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        let before = User::get_by_uuid(&id, conn).map_err(|_| UserStructError::NotFound.msg())?;
+        User::set_role(&id, form.role, conn).map_err(|e| e.msg())?;
+        let after = User::get_by_uuid(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::UserRoleChanged,
+            Some(&id),
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zmieniono rolę konta.")
+    })
+    .await
+}
+
+pub async fn delete_user_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        if user.id == id {
+            return Err("Nie możesz usunąć własnego konta.");
+        }
+        let before = User::get_by_uuid(&id, conn).map_err(|_| UserStructError::NotFound.msg())?;
+        User::delete(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::UserDeleted,
+            Some(&id),
+            snapshot(&before),
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Usunięto konto.")
+    })
+    .await
+}
+
+pub async fn revoke_session_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/sesje", move |conn, user| {
+        user.require_session().map_err(|e| e.msg())?;
+        let session =
+            Session::get_by_id(&id, conn).map_err(|_| SessionStructError::NotFound.msg())?;
+        if session.user_id() != &user.id {
+            user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        }
+        Session::revoke(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::SessionRevoked,
+            Some(&id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Unieważniono sesję.")
+    })
+    .await
+}
+
+pub async fn revoke_other_sessions_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    let current = cookie_token(&headers).map(str::to_owned);
+    panel_redir(&state, headers, "/panel/sesje", move |conn, user| {
+        let current = current
+            .and_then(|t| Session::get_by_token(&t, conn).ok())
+            .ok_or("Ta operacja wymaga zalogowania się przez formularz.")?;
+        Session::revoke_others(&user.id, current.id(), conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::OtherSessionsRevoked,
+            Some(&user.id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Wylogowano wszystkie pozostałe sesje.")
+    })
+    .await
+}
+
+pub async fn run_job_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    let Some(job) = scheduler::find(&name) else {
+        return Flash::error("Takie zadanie nie istnieje.")
+            .redirect("/panel/zadania")
+            .into_response();
     };
-    let cookie = format!(
-        "{COOKIE_NAME}={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
-        60 * 60 * 24 * 30, // 30 days in seconds
-        secure
-    );
-    ([(header::SET_COOKIE, cookie)], Redirect::to("/panel")).into_response()
+    let user = state
+        .with_db(move |conn| User::authenticate(&headers, conn))
+        .await;
+    let user = match user {
+        Ok(Ok(Some(u))) => u,
+        Ok(Ok(None)) => return Redirect::to("/panel").into_response(),
+        Ok(Err(e)) => return Flash::error(e.msg()).redirect("/panel").into_response(),
+        Err(_) => {
+            return Flash::error(SERVER_ERROR)
+                .redirect("/panel/zadania")
+                .into_response();
+        }
+    };
+    if let Err(e) = user.require(Permission::ManageJobs) {
+        return Flash::error(e.msg()).redirect("/panel").into_response();
+    }
+
+    let outcome = scheduler::run(&state, job).await;
+    let after = serde_json::json!({
+        "job": job.name,
+        "ok": outcome.is_ok(),
+        "message": outcome.as_ref().unwrap_or_else(|e| e),
+    });
+    let logged = state
+        .with_db(move |conn| {
+            audit::record(
+                conn,
+                Some(&user.id),
+                Action::JobRun,
+                None,
+                None,
+                Some(after),
+            )
+        })
+        .await;
+    if let Ok(Err(e)) = logged {
+        eprintln!("failed to log job run: {e}");
+    }
+    match outcome {
+        Ok(msg) => Flash::success(msg)
+            .redirect("/panel/zadania")
+            .into_response(),
+        Err(msg) => Flash::error(msg).redirect("/panel/zadania").into_response(),
+    }
+}
+
+/// Creates an API token and shows its secret right away, as it can't be looked up later.
+pub async fn new_api_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<HashMap<String, String>>,
+) -> Response {
+    panel_page(
+        &state,
+        headers,
+        "/panel/ustawienia",
+        move |conn, user, csrf| {
+            user.require_session().map_err(|e| e.msg())?;
+            let name = form.get("name").map(String::as_str).unwrap_or_default();
+            let scopes = Scope::ALL
+                .into_iter()
+                .filter(|s| form.contains_key(s.as_str()))
+                .collect();
+
+            let (token, secret) =
+                ApiToken::create(&user.id, name, scopes, conn).map_err(|e| e.msg())?;
+            audit::record(
+                conn,
+                Some(&user.id),
+                Action::ApiTokenCreated,
+                Some(&token.id),
+                None,
+                snapshot(&token),
+            )
+            .map_err(|e| e.msg())?;
+            Ok(new_token_page(
+                &config::competition_name(conn),
+                user,
+                &token,
+                &secret,
+                csrf,
+            ))
+        },
+    )
+    .await
+}
+
+pub async fn revoke_api_token_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require_session().map_err(|e| e.msg())?;
+        ApiToken::revoke(&id, &user.id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ApiTokenRevoked,
+            Some(&id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Unieważniono token API.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeForm {
+    code: String,
+}
+
+pub async fn begin_totp_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require_session().map_err(|e| e.msg())?;
+        totp::begin_enrolment(&user.id, conn).map_err(|e| e.msg())?;
+        Ok("Dodaj klucz do aplikacji uwierzytelniającej i potwierdź go kodem.")
+    })
+    .await
+}
+
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<TotpCodeForm>,
+) -> Response {
+    panel_page(
+        &state,
+        headers,
+        "/panel/ustawienia",
+        move |conn, user, csrf| {
+            user.require_session().map_err(|e| e.msg())?;
+            let codes = totp::confirm_enrolment(&user.id, &form.code, conn).map_err(|e| e.msg())?;
+            audit::record(
+                conn,
+                Some(&user.id),
+                Action::TotpEnabled,
+                Some(&user.id),
+                None,
+                None,
+            )
+            .map_err(|e| e.msg())?;
+            Ok(recovery_codes_page(
+                &config::competition_name(conn),
+                user,
+                &codes,
+                csrf,
+            ))
+        },
+    )
+    .await
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<TotpCodeForm>,
+) -> Response {
+    panel_page(
+        &state,
+        headers,
+        "/panel/ustawienia",
+        move |conn, user, csrf| {
+            user.require_session().map_err(|e| e.msg())?;
+            let codes =
+                totp::regenerate_recovery_codes(&user.id, &form.code, conn).map_err(|e| e.msg())?;
+            audit::record(
+                conn,
+                Some(&user.id),
+                Action::RecoveryCodesRegenerated,
+                Some(&user.id),
+                None,
+                None,
+            )
+            .map_err(|e| e.msg())?;
+            Ok(recovery_codes_page(
+                &config::competition_name(conn),
+                user,
+                &codes,
+                csrf,
+            ))
+        },
+    )
+    .await
+}
+
+pub async fn disable_totp_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<TotpCodeForm>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require_session().map_err(|e| e.msg())?;
+        totp::disable(user, &form.code, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::TotpDisabled,
+            Some(&user.id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Wyłączono weryfikację dwuetapową.")
+    })
+    .await
+}
+
+pub async fn reset_user_totp_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    _: CsrfForm<NoFields>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        totp::reset(&id, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::TotpDisabled,
+            Some(&id),
+            None,
+            None,
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Wyłączono weryfikację dwuetapową konta.")
+    })
+    .await
+}
+
+pub async fn totp_roles_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<HashMap<String, String>>,
+) -> Response {
+    panel_redir(&state, headers, "/panel/ustawienia", move |conn, user| {
+        user.require(Permission::ManageUsers).map_err(|e| e.msg())?;
+        let before = totp::required_roles(conn).map_err(|e| e.msg())?;
+        let roles = Role::ALL
+            .into_iter()
+            .filter(|r| form.contains_key(r.as_str()))
+            .collect::<Vec<_>>();
+        totp::set_required_roles(&roles, conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::TotpRolesChanged,
+            None,
+            snapshot(&before),
+            snapshot(&roles),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zapisano wymagania weryfikacji dwuetapowej.")
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ConfigForm {
+    name: String,
+    defcontramt: String,
+    start: String,
+    end: String,
+    stats_public: Option<String>,
+    show_sum: Option<String>,
+    show_timeline: Option<String>,
+    logo_url: String,
+}
+
+pub async fn save_config_redir(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    CsrfForm(form): CsrfForm<ConfigForm>,
+) -> Response {
+    stats_redir(&state, headers, "/panel", move |conn, user| {
+        user.require(Permission::ManageConfig)
+            .map_err(|e| e.msg())?;
+        let before = Config::load(conn).map_err(|e| e.msg())?;
+        let after = Config {
+            competition_name: form.name.trim().to_owned(),
+            default_contribution_amount: form
+                .defcontramt
+                .parse()
+                .map_err(|e: MoneyError| e.msg())?,
+            campaign_start: parse_date(&form.start).map_err(|e| e.msg())?,
+            campaign_end: parse_date(&form.end).map_err(|e| e.msg())?,
+            stats_public: form.stats_public.is_some(),
+            show_sum: form.show_sum.is_some(),
+            show_timeline: form.show_timeline.is_some(),
+            logo_url: Some(form.logo_url.trim().to_owned()).filter(|u| !u.is_empty()),
+        };
+        after.save(conn).map_err(|e| e.msg())?;
+        audit::record(
+            conn,
+            Some(&user.id),
+            Action::ConfigChanged,
+            None,
+            snapshot(&before),
+            snapshot(&after),
+        )
+        .map_err(|e| e.msg())?;
+        Ok("Zapisano ustawienia zbiorywalizacji.")
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+
+    #[test]
+    fn resubmitted_forms_record_one_contribution() {
+        let conn = test_db();
+        let id = Uuid::now_v7();
+        conn.execute(
+            "INSERT INTO users (id, handle, passhash, role) VALUES (?1, 'ala', '', 'counter')",
+            [id.to_string()],
+        )
+        .unwrap();
+        let user = User::get_by_uuid(&id, &conn).unwrap();
+        let container = Container::create("Puszka", &conn).unwrap().id;
+        let key = Uuid::now_v7();
+        let amount = "12,50".parse().unwrap();
+
+        let (first, new) =
+            record_contribution(&conn, &user, &container, amount, None, Some(&key), None).unwrap();
+        assert!(new);
+        let (again, new) =
+            record_contribution(&conn, &user, &container, amount, None, Some(&key), None).unwrap();
+        assert!(!new);
+        assert_eq!(again.id, first.id);
+        let (other, new) = record_contribution(
+            &conn,
+            &user,
+            &container,
+            amount,
+            None,
+            Some(&Uuid::now_v7()),
+            None,
+        )
+        .unwrap();
+        assert!(new);
+        assert_ne!(other.id, first.id);
+
+        let count: i64 = conn
+            .query_one("SELECT COUNT(*) FROM contributions", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
 }
@@ -0,0 +1,289 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, ToSql};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Every state-changing operation that ends up in the `logs` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Login,
+    LoginFailed,
+    Logout,
+    SessionRevoked,
+    OtherSessionsRevoked,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+    TotpEnabled,
+    TotpDisabled,
+    RecoveryCodesRegenerated,
+    TotpRolesChanged,
+    ConfigChanged,
+    ContributionCreated,
+    ContributionCorrected,
+    ContributionVoided,
+    ContributionUndone,
+    ContainerCreated,
+    ContainerRenamed,
+    ContainerArchived,
+    ContainerUnarchived,
+    ContainerDeleted,
+    UserCreated,
+    UserPasswordChanged,
+    UserPasswordReset,
+    UserRoleChanged,
+    UserDeleted,
+    JobRun,
+}
+
+impl Action {
+    pub const ALL: [Action; 27] = [
+        Action::Login,
+        Action::LoginFailed,
+        Action::Logout,
+        Action::SessionRevoked,
+        Action::OtherSessionsRevoked,
+        Action::ApiTokenCreated,
+        Action::ApiTokenRevoked,
+        Action::TotpEnabled,
+        Action::TotpDisabled,
+        Action::RecoveryCodesRegenerated,
+        Action::TotpRolesChanged,
+        Action::ConfigChanged,
+        Action::ContributionCreated,
+        Action::ContributionCorrected,
+        Action::ContributionVoided,
+        Action::ContributionUndone,
+        Action::ContainerCreated,
+        Action::ContainerRenamed,
+        Action::ContainerArchived,
+        Action::ContainerUnarchived,
+        Action::ContainerDeleted,
+        Action::UserCreated,
+        Action::UserPasswordChanged,
+        Action::UserPasswordReset,
+        Action::UserRoleChanged,
+        Action::UserDeleted,
+        Action::JobRun,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Login => "session.login",
+            Action::LoginFailed => "session.login_failed",
+            Action::Logout => "session.logout",
+            Action::SessionRevoked => "session.revoke",
+            Action::OtherSessionsRevoked => "session.revoke_others",
+            Action::ApiTokenCreated => "api_token.create",
+            Action::ApiTokenRevoked => "api_token.revoke",
+            Action::TotpEnabled => "user.totp_enable",
+            Action::TotpDisabled => "user.totp_disable",
+            Action::RecoveryCodesRegenerated => "user.recovery_codes",
+            Action::TotpRolesChanged => "config.totp_roles",
+            Action::ConfigChanged => "config.update",
+            Action::ContributionCreated => "contribution.create",
+            Action::ContributionCorrected => "contribution.correct",
+            Action::ContributionVoided => "contribution.void",
+            Action::ContributionUndone => "contribution.undo",
+            Action::ContainerCreated => "container.create",
+            Action::ContainerRenamed => "container.rename",
+            Action::ContainerArchived => "container.archive",
+            Action::ContainerUnarchived => "container.unarchive",
+            Action::ContainerDeleted => "container.delete",
+            Action::UserCreated => "user.create",
+            Action::UserPasswordChanged => "user.password",
+            Action::UserPasswordReset => "user.password_reset",
+            Action::UserRoleChanged => "user.role",
+            Action::UserDeleted => "user.delete",
+            Action::JobRun => "job.run",
+        }
+    }
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Action::Login => "Zalogowanie",
+            Action::LoginFailed => "Nieudane logowanie",
+            Action::Logout => "Wylogowanie",
+            Action::SessionRevoked => "Unieważnienie sesji",
+            Action::OtherSessionsRevoked => "Wylogowanie pozostałych sesji",
+            Action::ApiTokenCreated => "Utworzenie tokenu API",
+            Action::ApiTokenRevoked => "Unieważnienie tokenu API",
+            Action::TotpEnabled => "Włączenie weryfikacji dwuetapowej",
+            Action::TotpDisabled => "Wyłączenie weryfikacji dwuetapowej",
+            Action::RecoveryCodesRegenerated => "Nowe kody odzyskiwania",
+            Action::TotpRolesChanged => "Zmiana ról wymagających weryfikacji dwuetapowej",
+            Action::ConfigChanged => "Zmiana ustawień zbiorywalizacji",
+            Action::ContributionCreated => "Odnotowanie datku",
+            Action::ContributionCorrected => "Korekta datku",
+            Action::ContributionVoided => "Unieważnienie datku",
+            Action::ContributionUndone => "Cofnięcie datku",
+            Action::ContainerCreated => "Utworzenie pojemnika",
+            Action::ContainerRenamed => "Zmiana nazwy pojemnika",
+            Action::ContainerArchived => "Archiwizacja pojemnika",
+            Action::ContainerUnarchived => "Przywrócenie pojemnika",
+            Action::ContainerDeleted => "Usunięcie pojemnika",
+            Action::UserCreated => "Utworzenie konta",
+            Action::UserPasswordChanged => "Zmiana hasła",
+            Action::UserPasswordReset => "Reset hasła",
+            Action::UserRoleChanged => "Zmiana roli",
+            Action::UserDeleted => "Usunięcie konta",
+            Action::JobRun => "Ręczne uruchomienie zadania",
+        }
+    }
+    /// Kind of the entity the action was performed on, as stored in `logs.entity_type`.
+    pub fn entity_type(&self) -> &'static str {
+        match self.as_str().split_once('.') {
+            Some((entity, _)) => entity,
+            None => self.as_str(),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL.into_iter().find(|a| a.as_str() == s).ok_or(())
+    }
+}
+
+#[derive(Debug)]
+pub struct LogEntry {
+    pub at: DateTime<Utc>,
+    pub actor: Option<Uuid>,
+    pub actor_handle: Option<String>,
+    pub action: Action,
+    pub entity_id: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuditError {
+    #[error("Failed to execute SQL: {0}")]
+    AuditSqlError(#[from] rusqlite::Error),
+    #[error("Malformed log entry found in DB")]
+    MalformedEntry,
+}
+impl AuditError {
+    pub fn msg(&self) -> &'static str {
+        "Błąd serwera. Skontaktuj się z webmasterem."
+    }
+}
+
+/// Serializes a before/after snapshot for [`record`].
+pub fn snapshot(value: &impl Serialize) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// Writes an entry to the audit log. Call it on the same connection (and transaction)
+/// as the change itself, so that the change and its log entry land together.
+pub fn record(
+    conn: &Connection,
+    actor: Option<&Uuid>,
+    action: Action,
+    entity_id: Option<&Uuid>,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<(), AuditError> {
+    conn.prepare(
+        "INSERT INTO logs (id, at, actor, action, entity_type, entity_id, before, after)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?
+    .execute(rusqlite::params![
+        Uuid::now_v7().to_string(),
+        Utc::now().timestamp(),
+        actor.map(|a| a.to_string()),
+        action.as_str(),
+        action.entity_type(),
+        entity_id.map(|e| e.to_string()),
+        before.map(|b| b.to_string()),
+        after.map(|a| a.to_string()),
+    ])?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub action: Option<Action>,
+    pub actor: Option<Uuid>,
+    pub entity_id: Option<String>,
+}
+
+/// A page of log entries matching `filter`, newest first, plus the total match count.
+pub fn query(
+    conn: &Connection,
+    filter: &LogFilter,
+    limit: u32,
+    offset: u32,
+) -> Result<(Vec<LogEntry>, u64), AuditError> {
+    let mut conditions = Vec::new();
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+    if let Some(action) = filter.action {
+        params.push(Box::new(action.as_str()));
+        conditions.push(format!("l.action = ?{}", params.len()));
+    }
+    if let Some(actor) = filter.actor {
+        params.push(Box::new(actor.to_string()));
+        conditions.push(format!("l.actor = ?{}", params.len()));
+    }
+    if let Some(entity_id) = &filter.entity_id {
+        params.push(Box::new(entity_id.clone()));
+        conditions.push(format!("l.entity_id = ?{}", params.len()));
+    }
+    let where_clause = match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    };
+
+    let total = conn
+        .prepare(&format!("SELECT COUNT(*) FROM logs l {where_clause}"))?
+        .query_one(rusqlite::params_from_iter(params.iter()), |r| {
+            r.get::<_, u64>(0)
+        })?;
+
+    params.push(Box::new(limit));
+    params.push(Box::new(offset));
+    let query = format!(
+        "SELECT l.at, l.actor, u.handle, l.action, l.entity_id, l.before, l.after
+         FROM logs l LEFT JOIN users u ON u.id = l.actor
+         {where_clause}
+         ORDER BY l.id DESC
+         LIMIT ?{} OFFSET ?{}",
+        params.len() - 1,
+        params.len()
+    );
+    let rows = conn
+        .prepare(&query)?
+        .query_map(rusqlite::params_from_iter(params.iter()), |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, Option<String>>(1)?,
+                r.get::<_, Option<String>>(2)?,
+                r.get::<_, String>(3)?,
+                r.get::<_, Option<String>>(4)?,
+                r.get::<_, Option<String>>(5)?,
+                r.get::<_, Option<String>>(6)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let entries = rows
+        .into_iter()
+        .map(
+            |(at, actor, actor_handle, action, entity_id, before, after)| {
+                Ok(LogEntry {
+                    at: DateTime::from_timestamp(at, 0).ok_or(AuditError::MalformedEntry)?,
+                    actor: actor
+                        .map(|a| Uuid::from_str(&a).map_err(|_| AuditError::MalformedEntry))
+                        .transpose()?,
+                    actor_handle,
+                    action: Action::from_str(&action).map_err(|_| AuditError::MalformedEntry)?,
+                    entity_id,
+                    before,
+                    after,
+                })
+            },
+        )
+        .collect::<Result<Vec<_>, AuditError>>()?;
+    Ok((entries, total))
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use rusqlite::{
+    ToSql,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::money::Money;
+
+/// Polish coins and notes in circulation, in grosze, smallest first.
+pub const DENOMINATIONS: [i64; 15] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 20000, 50000,
+];
+/// The smallest note, 10 zł; everything below it is a coin.
+pub const SMALLEST_NOTE: i64 = 1000;
+/// Prefix of the form fields holding quantities, followed by the denomination in grosze.
+pub const FIELD_PREFIX: &str = "szt";
+/// More of one denomination than fits in any container, so surely a typo.
+const MAX_QUANTITY: u32 = 100_000;
+
+/// How many of each coin and note were counted out of a container. Kept with the
+/// contribution it was recorded as, for reconciling the cash later; stored as a JSON
+/// object of denominations in grosze to quantities.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CashCount(BTreeMap<i64, u32>);
+
+#[derive(thiserror::Error, Debug)]
+pub enum CashError {
+    #[error("Unknown denomination")]
+    UnknownDenomination,
+    #[error("Invalid quantity")]
+    InvalidQuantity,
+    #[error("Quantity is too large")]
+    TooMany,
+    #[error("Nothing was counted")]
+    Empty,
+    #[error("Total is too large")]
+    TooLarge,
+    #[error("Counted total does not match the declared one")]
+    Mismatch,
+}
+impl CashError {
+    pub fn msg(&self) -> &'static str {
+        match self {
+            CashError::UnknownDenomination => "Nieznany nominał.",
+            CashError::InvalidQuantity => "Liczba monet i banknotów musi być liczbą całkowitą.",
+            CashError::TooMany => "Za dużo monet lub banknotów jednego nominału.",
+            CashError::Empty => "Nie wpisano żadnych monet ani banknotów.",
+            CashError::TooLarge => "Kwota jest za duża.",
+            CashError::Mismatch => "Przeliczona kwota nie zgadza się z zadeklarowaną.",
+        }
+    }
+}
+
+impl CashCount {
+    /// Reads quantities from form fields named [`FIELD_PREFIX`] and a denomination,
+    /// ignoring any other fields. Blank fields count as none.
+    pub fn from_fields<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<CashCount, CashError> {
+        let mut counts = BTreeMap::new();
+        for (name, quantity) in fields {
+            let Some(denomination) = name.strip_prefix(FIELD_PREFIX) else {
+                continue;
+            };
+            let denomination = denomination
+                .parse::<i64>()
+                .ok()
+                .filter(|d| DENOMINATIONS.contains(d))
+                .ok_or(CashError::UnknownDenomination)?;
+            let quantity = match quantity.trim() {
+                "" => 0,
+                q => q.parse::<u32>().map_err(|_| CashError::InvalidQuantity)?,
+            };
+            if quantity > MAX_QUANTITY {
+                return Err(CashError::TooMany);
+            }
+            if quantity > 0 {
+                counts.insert(denomination, quantity);
+            }
+        }
+        match counts.is_empty() {
+            true => Err(CashError::Empty),
+            false => Ok(CashCount(counts)),
+        }
+    }
+
+    /// The exact value of everything counted.
+    pub fn total(&self) -> Result<Money, CashError> {
+        self.0
+            .iter()
+            .try_fold(0i64, |sum, (denomination, quantity)| {
+                sum.checked_add(denomination.checked_mul(i64::from(*quantity))?)
+            })
+            .and_then(|grosze| Money::try_from(grosze).ok())
+            .ok_or(CashError::TooLarge)
+    }
+
+    /// The total, if it agrees with one declared for the container beforehand, e.g.
+    /// written on its seal.
+    pub fn check_declared(&self, declared: Money) -> Result<Money, CashError> {
+        match self.total()? {
+            total if total == declared => Ok(total),
+            _ => Err(CashError::Mismatch),
+        }
+    }
+
+    /// Number of coins and notes counted.
+    pub fn pieces(&self) -> u64 {
+        self.0.values().map(|&q| u64::from(q)).sum()
+    }
+
+    /// Denominations counted and their quantities, smallest first.
+    pub fn iter(&self) -> impl Iterator<Item = (i64, u32)> + '_ {
+        self.0.iter().map(|(&d, &q)| (d, q))
+    }
+}
+
+/// A denomination as printed on it, e.g. "20 gr" or "50 zł".
+pub fn denomination_label(grosze: i64) -> String {
+    match grosze < 100 {
+        true => format!("{grosze}\u{a0}gr"),
+        false => format!("{}\u{a0}zł", grosze / 100),
+    }
+}
+
+impl ToSql for CashCount {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(ToSqlOutput::from(json))
+    }
+}
+
+impl FromSql for CashCount {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        serde_json::from_str(value.as_str()?).map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(fields: &[(&str, &str)]) -> Result<CashCount, CashError> {
+        CashCount::from_fields(fields.iter().copied())
+    }
+
+    #[test]
+    fn totals_a_mixed_count() {
+        let count = count(&[
+            ("szt1", "3"),
+            ("szt50", "2"),
+            ("szt500", " 1 "),
+            ("szt2000", "4"),
+            ("szt20000", ""),
+            ("csrf", "whatever"),
+        ])
+        .unwrap();
+        assert_eq!(count.total().unwrap(), "86,03".parse().unwrap());
+        assert_eq!(count.pieces(), 10);
+        assert_eq!(
+            count.iter().collect::<Vec<_>>(),
+            [(1, 3), (50, 2), (500, 1), (2000, 4)]
+        );
+    }
+
+    #[test]
+    fn rejects_an_empty_count() {
+        assert!(matches!(count(&[]), Err(CashError::Empty)));
+        assert!(matches!(
+            count(&[("szt100", ""), ("szt200", "0"), ("contrnote", "x")]),
+            Err(CashError::Empty)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        assert!(matches!(
+            count(&[("szt3", "1")]),
+            Err(CashError::UnknownDenomination)
+        ));
+        assert!(matches!(
+            count(&[("sztx", "1")]),
+            Err(CashError::UnknownDenomination)
+        ));
+        for quantity in ["-1", "1.5", "dużo"] {
+            assert!(matches!(
+                count(&[("szt100", quantity)]),
+                Err(CashError::InvalidQuantity)
+            ));
+        }
+        assert!(matches!(
+            count(&[("szt100", "100001")]),
+            Err(CashError::TooMany)
+        ));
+    }
+
+    #[test]
+    fn checks_the_declared_total() {
+        let count = count(&[("szt200", "5"), ("szt1000", "1")]).unwrap();
+        assert_eq!(
+            count.check_declared("20".parse().unwrap()).unwrap(),
+            "20".parse().unwrap()
+        );
+        assert!(matches!(
+            count.check_declared("20,01".parse().unwrap()),
+            Err(CashError::Mismatch)
+        ));
+        assert!(matches!(
+            count.check_declared(Money::ZERO),
+            Err(CashError::Mismatch)
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::money::Money;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const MAX_NAME_LEN: usize = 80;
+/// What the competition is called until someone renames it.
+pub const DEFAULT_NAME: &str = "Zbiorywalizacja WPiK";
+
+/// Instance configuration, kept in the single row of the `config` table. Two-factor
+/// requirements live there too, but belong to [`crate::users::totp`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    /// Shown as the title of the public stats page and the panel.
+    pub competition_name: String,
+    /// Prefilled in the new contribution form.
+    pub default_contribution_amount: Money,
+    pub campaign_start: Option<NaiveDate>,
+    /// Last day of the campaign, inclusive.
+    pub campaign_end: Option<NaiveDate>,
+    /// Whether visitors who aren't logged in can see the stats page.
+    pub stats_public: bool,
+    pub show_sum: bool,
+    pub show_timeline: bool,
+    /// Image shown in the logo tile of the stats page.
+    pub logo_url: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to execute SQL: {0}")]
+    ConfigSqlError(#[from] rusqlite::Error),
+    #[error("Malformed date found in config")]
+    MalformedDate,
+    #[error("Competition name must not be empty")]
+    EmptyName,
+    #[error("Competition name is too long")]
+    NameTooLong,
+    #[error("Default contribution amount must be positive")]
+    InvalidAmount,
+    #[error("Invalid date")]
+    InvalidDate,
+    #[error("Campaign ends before it starts")]
+    EndBeforeStart,
+    #[error("Logo URL must be an http(s) address or a path on this server")]
+    InvalidLogoUrl,
+}
+impl ConfigError {
+    pub fn msg(&self) -> &'static str {
+        match self {
+            ConfigError::ConfigSqlError(_) | ConfigError::MalformedDate => {
+                "Błąd serwera. Skontaktuj się z webmasterem."
+            }
+            ConfigError::EmptyName => "Nazwa zbiorywalizacji nie może być pusta.",
+            ConfigError::NameTooLong => "Nazwa zbiorywalizacji jest za długa.",
+            ConfigError::InvalidAmount => "Nieprawidłowa domyślna wielkość datku.",
+            ConfigError::InvalidDate => "Nieprawidłowa data.",
+            ConfigError::EndBeforeStart => "Zbiórka nie może skończyć się przed rozpoczęciem.",
+            ConfigError::InvalidLogoUrl => {
+                "Adres logo musi zaczynać się od http://, https:// albo /."
+            }
+        }
+    }
+}
+
+/// Parses a date as sent by `<input type="date">`, treating a blank field as no date.
+pub fn parse_date(input: &str) -> Result<Option<NaiveDate>, ConfigError> {
+    match input.trim() {
+        "" => Ok(None),
+        d => NaiveDate::parse_from_str(d, DATE_FORMAT)
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidDate),
+    }
+}
+
+/// The competition's name, or [`DEFAULT_NAME`] if it can't be read, for pages that have
+/// to render regardless, such as error pages or ones showing secrets only once.
+pub fn competition_name(conn: &Connection) -> String {
+    conn.prepare("SELECT competition_name FROM config WHERE id_zero = 0")
+        .and_then(|mut s| s.query_one([], |r| r.get(0)))
+        .unwrap_or_else(|_| DEFAULT_NAME.to_owned())
+}
+
+impl Config {
+    pub fn load(conn: &Connection) -> Result<Config, ConfigError> {
+        let (config, start, end) = conn
+            .prepare(
+                "SELECT competition_name, default_contribution_amount, campaign_start,
+                        campaign_end, stats_public, show_sum, show_timeline, logo_url
+                 FROM config WHERE id_zero = 0",
+            )?
+            .query_one([], |r| {
+                Ok((
+                    Config {
+                        competition_name: r.get(0)?,
+                        default_contribution_amount: r.get(1)?,
+                        campaign_start: None,
+                        campaign_end: None,
+                        stats_public: r.get(4)?,
+                        show_sum: r.get(5)?,
+                        show_timeline: r.get(6)?,
+                        logo_url: r.get(7)?,
+                    },
+                    r.get::<_, Option<String>>(2)?,
+                    r.get::<_, Option<String>>(3)?,
+                ))
+            })?;
+        let date = |d: Option<String>| {
+            parse_date(d.as_deref().unwrap_or_default()).map_err(|_| ConfigError::MalformedDate)
+        };
+        Ok(Config {
+            campaign_start: date(start)?,
+            campaign_end: date(end)?,
+            ..config
+        })
+    }
+    /// Checks the configuration and writes it over the stored one.
+    pub fn save(&self, conn: &Connection) -> Result<(), ConfigError> {
+        self.validate()?;
+        let date = |d: Option<NaiveDate>| d.map(|d| d.format(DATE_FORMAT).to_string());
+        conn.prepare(
+            "UPDATE config SET competition_name = ?1, default_contribution_amount = ?2,
+                campaign_start = ?3, campaign_end = ?4, stats_public = ?5, show_sum = ?6,
+                show_timeline = ?7, logo_url = ?8
+             WHERE id_zero = 0",
+        )?
+        .execute(rusqlite::params![
+            self.competition_name,
+            self.default_contribution_amount,
+            date(self.campaign_start),
+            date(self.campaign_end),
+            self.stats_public,
+            self.show_sum,
+            self.show_timeline,
+            self.logo_url,
+        ])?;
+        Ok(())
+    }
+    fn validate(&self) -> Result<(), ConfigError> {
+        match self.competition_name.trim() {
+            "" => return Err(ConfigError::EmptyName),
+            n if n.chars().count() > MAX_NAME_LEN => return Err(ConfigError::NameTooLong),
+            _ => (),
+        }
+        if self.default_contribution_amount <= Money::ZERO {
+            return Err(ConfigError::InvalidAmount);
+        }
+        if let (Some(start), Some(end)) = (self.campaign_start, self.campaign_end)
+            && end < start
+        {
+            return Err(ConfigError::EndBeforeStart);
+        }
+        if let Some(url) = &self.logo_url
+            && !["http://", "https://", "/"]
+                .iter()
+                .any(|p| url.starts_with(p))
+        {
+            return Err(ConfigError::InvalidLogoUrl);
+        }
+        Ok(())
+    }
+}
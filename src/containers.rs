@@ -0,0 +1,163 @@
+use std::str::FromStr;
+
+use rusqlite::{Connection, ErrorCode, OptionalExtension};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct Container {
+    pub id: Uuid,
+    pub name: String,
+    pub archived: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ContainerStructError {
+    #[error("Failed to execute SQL: {0}")]
+    ContainerSqlError(#[from] rusqlite::Error),
+    #[error("Non-UUID Container PK found in DB")]
+    NonUuidPrimaryKey,
+    #[error("Container name must not be empty")]
+    EmptyName,
+    #[error("Container name is already taken")]
+    NameTaken,
+    #[error("Container does not exist")]
+    NotFound,
+    #[error("Container is referenced by contributions")]
+    InUse,
+}
+impl ContainerStructError {
+    pub fn msg(&self) -> &'static str {
+        use ContainerStructError as CSE;
+        match self {
+            CSE::ContainerSqlError(_) | CSE::NonUuidPrimaryKey => {
+                "Błąd serwera. Skontaktuj się z webmasterem."
+            }
+            CSE::EmptyName => "Nazwa pojemnika nie może być pusta.",
+            CSE::NameTaken => "Pojemnik o tej nazwie już istnieje.",
+            CSE::NotFound => "Taki pojemnik nie istnieje.",
+            CSE::InUse => {
+                "Nie można usunąć pojemnika, do którego odnotowano datki. Zarchiwizuj go."
+            }
+        }
+    }
+}
+
+/// Maps UNIQUE constraint failures on `containers.name` to a friendlier error.
+fn map_unique(e: rusqlite::Error) -> ContainerStructError {
+    match e.sqlite_error_code() {
+        Some(ErrorCode::ConstraintViolation) => ContainerStructError::NameTaken,
+        _ => ContainerStructError::ContainerSqlError(e),
+    }
+}
+
+fn normalize_name(name: &str) -> Result<&str, ContainerStructError> {
+    match name.trim() {
+        "" => Err(ContainerStructError::EmptyName),
+        n => Ok(n),
+    }
+}
+
+impl Container {
+    pub fn create(name: &str, conn: &Connection) -> Result<Container, ContainerStructError> {
+        let container = Container {
+            id: Uuid::now_v7(),
+            name: normalize_name(name)?.to_owned(),
+            archived: false,
+        };
+        conn.prepare("INSERT INTO containers (id, name, archived) VALUES (?1, ?2, 0)")?
+            .execute([container.id.to_string(), container.name.clone()])
+            .map_err(map_unique)?;
+        Ok(container)
+    }
+    pub fn rename(id: &Uuid, name: &str, conn: &Connection) -> Result<(), ContainerStructError> {
+        let name = normalize_name(name)?;
+        let changed = conn
+            .prepare("UPDATE containers SET name = ?2 WHERE id = ?1")?
+            .execute([id.to_string(), name.to_owned()])
+            .map_err(map_unique)?;
+        match changed {
+            0 => Err(ContainerStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    pub fn set_archived(
+        id: &Uuid,
+        archived: bool,
+        conn: &Connection,
+    ) -> Result<(), ContainerStructError> {
+        let changed = conn
+            .prepare("UPDATE containers SET archived = ?2 WHERE id = ?1")?
+            .execute(rusqlite::params![id.to_string(), archived])?;
+        match changed {
+            0 => Err(ContainerStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    /// Deletes a container, refusing to do so if any contribution references it.
+    pub fn delete(id: &Uuid, conn: &Connection) -> Result<(), ContainerStructError> {
+        let pk = id.to_string();
+        let uses = conn
+            .prepare("SELECT COUNT(*) FROM contributions WHERE container = ?1")?
+            .query_one([&pk], |r| r.get::<_, u64>(0))?;
+        if uses > 0 {
+            return Err(ContainerStructError::InUse);
+        }
+        match conn
+            .prepare("DELETE FROM containers WHERE id = ?1")?
+            .execute([&pk])?
+        {
+            0 => Err(ContainerStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    pub fn get_by_id(id: &Uuid, conn: &Connection) -> Result<Container, ContainerStructError> {
+        let (name, archived) = conn
+            .prepare("SELECT name, archived FROM containers WHERE id = ?1")?
+            .query_one([id.to_string()], |r| {
+                Ok((r.get::<_, String>(0)?, r.get::<_, bool>(1)?))
+            })
+            .optional()?
+            .ok_or(ContainerStructError::NotFound)?;
+        Ok(Container {
+            id: *id,
+            name,
+            archived,
+        })
+    }
+    /// All containers, archived ones included, ordered by name.
+    pub fn get_all(conn: &Connection) -> Result<Vec<Container>, ContainerStructError> {
+        Self::query(
+            "SELECT id, name, archived FROM containers ORDER BY name",
+            conn,
+        )
+    }
+    /// Containers that can still receive new contributions.
+    pub fn get_active(conn: &Connection) -> Result<Vec<Container>, ContainerStructError> {
+        Self::query(
+            "SELECT id, name, archived FROM containers WHERE archived = 0 ORDER BY name",
+            conn,
+        )
+    }
+    fn query(sql: &str, conn: &Connection) -> Result<Vec<Container>, ContainerStructError> {
+        let rows = conn
+            .prepare(sql)?
+            .query_map([], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, bool>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(id, name, archived)| {
+                Ok(Container {
+                    id: Uuid::from_str(&id).map_err(|_| ContainerStructError::NonUuidPrimaryKey)?,
+                    name,
+                    archived,
+                })
+            })
+            .collect()
+    }
+}
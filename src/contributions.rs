@@ -0,0 +1,671 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, ToSql};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    cash::CashCount,
+    money::Money,
+    users::{User, auth::AuthError, roles::Permission},
+};
+
+const MAX_REASON_LEN: usize = 500;
+/// How long after recording a contribution its recorder can still take it back in one click.
+pub const UNDO_WINDOW_SECS: i64 = 120;
+const UNDO_REASON: &str = "Cofnięty zaraz po odnotowaniu.";
+
+#[derive(Debug, Serialize)]
+pub struct Contribution {
+    pub id: Uuid,
+    pub container: Uuid,
+    pub amount: Money,
+    pub notes: Option<String>,
+    pub recorded_by: Uuid,
+    pub recorded_at: DateTime<Utc>,
+    /// The coins and notes, if the contribution was counted out in the panel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cash_count: Option<CashCount>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ContributionStructError {
+    #[error("Failed to execute SQL: {0}")]
+    ContributionSqlError(#[from] rusqlite::Error),
+    #[error("Referenced container does not exist")]
+    UnknownContainer,
+    #[error("Referenced container is archived")]
+    ArchivedContainer,
+    #[error("Contribution does not exist")]
+    NotFound,
+    #[error("Malformed contribution found in DB")]
+    MalformedEntry,
+    #[error("Amount must be positive")]
+    InvalidAmount,
+    #[error("Amount is the same as before")]
+    UnchangedAmount,
+    #[error("A reason must be given")]
+    MissingReason,
+    #[error("Reason is too long")]
+    ReasonTooLong,
+    #[error("Contribution has been voided")]
+    Voided,
+    #[error("Idempotency key was used by someone else")]
+    ForeignKey,
+    #[error("Contribution was recorded by someone else")]
+    NotUndoable,
+    #[error("Contribution is too old to undo")]
+    UndoExpired,
+}
+
+impl ContributionStructError {
+    pub fn msg(&self) -> &'static str {
+        use ContributionStructError as CSE;
+        match self {
+            CSE::ContributionSqlError(_) => "Nie udało się odnotować datku.",
+            CSE::UnknownContainer => "Wybrany pojemnik nie istnieje.",
+            CSE::ArchivedContainer => "Wybrany pojemnik jest zarchiwizowany.",
+            CSE::NotFound => "Taki datek nie istnieje.",
+            CSE::MalformedEntry => "Błąd serwera. Skontaktuj się z webmasterem.",
+            CSE::InvalidAmount => "Nieprawidłowa wielkość datku.",
+            CSE::UnchangedAmount => "Nowa kwota jest taka sama jak dotychczasowa.",
+            CSE::MissingReason => "Podaj powód.",
+            CSE::ReasonTooLong => "Powód jest za długi.",
+            CSE::Voided => "Ten datek został unieważniony.",
+            CSE::ForeignKey => "Ten formularz został już wysłany przez kogoś innego.",
+            CSE::NotUndoable => "Możesz cofnąć tylko datki odnotowane przez siebie.",
+            CSE::UndoExpired => {
+                "Minął czas na cofnięcie datku. Możesz go unieważnić na jego stronie."
+            }
+        }
+    }
+}
+
+impl Contribution {
+    /// Record a new contribution into an existing container. The idempotency key, if
+    /// given, must not have been used before; see [`Contribution::get_by_idempotency_key`].
+    pub fn create(
+        container: &Uuid,
+        amount: Money,
+        notes: Option<String>,
+        recorded_by: &Uuid,
+        idempotency_key: Option<&Uuid>,
+        cash_count: Option<CashCount>,
+        conn: &Connection,
+    ) -> Result<Contribution, ContributionStructError> {
+        match conn
+            .prepare("SELECT archived FROM containers WHERE id = ?1")?
+            .query_one([container.to_string()], |r| r.get::<_, bool>(0))
+            .optional()?
+        {
+            None => return Err(ContributionStructError::UnknownContainer),
+            Some(true) => return Err(ContributionStructError::ArchivedContainer),
+            Some(false) => (),
+        }
+
+        let contribution = Contribution {
+            id: Uuid::now_v7(),
+            container: *container,
+            amount,
+            notes,
+            recorded_by: *recorded_by,
+            recorded_at: Utc::now(),
+            cash_count,
+        };
+        conn.prepare(
+            "INSERT INTO contributions
+                (id, container, amount, notes, recorded_by, recorded_at, idempotency_key,
+                 cash_count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute(rusqlite::params![
+            contribution.id.to_string(),
+            contribution.container.to_string(),
+            contribution.amount,
+            contribution.notes,
+            contribution.recorded_by.to_string(),
+            contribution.recorded_at.timestamp(),
+            idempotency_key.map(|k| k.to_string()),
+            contribution.cash_count,
+        ])?;
+
+        Ok(contribution)
+    }
+
+    /// The contribution recorded by `recorded_by` with the given idempotency key, if
+    /// any. Keys come from the forms contributions are recorded with, so that a form
+    /// sent twice can be answered with the contribution it already made.
+    pub fn get_by_idempotency_key(
+        key: &Uuid,
+        recorded_by: &Uuid,
+        conn: &Connection,
+    ) -> Result<Option<Contribution>, ContributionStructError> {
+        let row = conn
+            .prepare(
+                "SELECT id, container, amount, notes, recorded_by, recorded_at, cash_count
+                 FROM contributions WHERE idempotency_key = ?1",
+            )?
+            .query_one([key.to_string()], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, Option<String>>(1)?,
+                    r.get::<_, Money>(2)?,
+                    r.get::<_, Option<String>>(3)?,
+                    r.get::<_, Option<String>>(4)?,
+                    r.get::<_, i64>(5)?,
+                    r.get::<_, Option<CashCount>>(6)?,
+                ))
+            })
+            .optional()?;
+        let Some((id, container, amount, notes, by, at, cash_count)) = row else {
+            return Ok(None);
+        };
+        let uuid = |id: Option<&str>| {
+            id.and_then(|id| Uuid::from_str(id).ok())
+                .ok_or(ContributionStructError::MalformedEntry)
+        };
+        if uuid(by.as_deref())? != *recorded_by {
+            return Err(ContributionStructError::ForeignKey);
+        }
+        Ok(Some(Contribution {
+            id: uuid(Some(&id))?,
+            container: uuid(container.as_deref())?,
+            amount,
+            notes,
+            recorded_by: *recorded_by,
+            recorded_at: DateTime::from_timestamp(at, 0)
+                .ok_or(ContributionStructError::MalformedEntry)?,
+            cash_count,
+        }))
+    }
+
+    /// Changes the amount of a contribution, keeping the one first recorded. A cash count
+    /// no longer adds up to the new amount, so it's dropped; the audit log keeps it.
+    pub fn correct(
+        id: &Uuid,
+        amount: Money,
+        reason: &str,
+        corrected_by: &Uuid,
+        conn: &Connection,
+    ) -> Result<(), ContributionStructError> {
+        let reason = check_reason(reason)?;
+        if amount <= Money::ZERO {
+            return Err(ContributionStructError::InvalidAmount);
+        }
+        let entry = ContributionEntry::get(id, conn)?;
+        if entry.voided_at.is_some() {
+            return Err(ContributionStructError::Voided);
+        }
+        if entry.amount == amount {
+            return Err(ContributionStructError::UnchangedAmount);
+        }
+        // checked again here, in case it was voided since
+        let changed = conn
+            .prepare(
+                "UPDATE contributions SET original_amount = COALESCE(original_amount, amount),
+                    amount = ?2, cash_count = NULL, corrected_at = ?3, corrected_by = ?4,
+                    correction_reason = ?5
+                 WHERE id = ?1 AND voided_at IS NULL",
+            )?
+            .execute(rusqlite::params![
+                id.to_string(),
+                amount,
+                Utc::now().timestamp(),
+                corrected_by.to_string(),
+                reason,
+            ])?;
+        match changed {
+            0 => Err(ContributionStructError::Voided),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a contribution out of all totals. It stays in the history for good.
+    pub fn void(
+        id: &Uuid,
+        reason: &str,
+        voided_by: &Uuid,
+        conn: &Connection,
+    ) -> Result<(), ContributionStructError> {
+        let reason = check_reason(reason)?;
+        if ContributionEntry::get(id, conn)?.voided_at.is_some() {
+            return Err(ContributionStructError::Voided);
+        }
+        let changed = conn
+            .prepare(
+                "UPDATE contributions SET voided_at = ?2, voided_by = ?3, void_reason = ?4
+                 WHERE id = ?1 AND voided_at IS NULL",
+            )?
+            .execute(rusqlite::params![
+                id.to_string(),
+                Utc::now().timestamp(),
+                voided_by.to_string(),
+                reason,
+            ])?;
+        match changed {
+            0 => Err(ContributionStructError::Voided),
+            _ => Ok(()),
+        }
+    }
+
+    /// Voids a contribution its recorder has just made, within [`UNDO_WINDOW_SECS`],
+    /// without asking them for a reason.
+    pub fn undo(
+        id: &Uuid,
+        recorded_by: &Uuid,
+        conn: &Connection,
+    ) -> Result<(), ContributionStructError> {
+        let entry = ContributionEntry::get(id, conn)?;
+        if entry.recorded_by != Some(*recorded_by) {
+            return Err(ContributionStructError::NotUndoable);
+        }
+        if entry.voided_at.is_some() {
+            return Err(ContributionStructError::Voided);
+        }
+        if (Utc::now() - entry.recorded_at).num_seconds() > UNDO_WINDOW_SECS {
+            return Err(ContributionStructError::UndoExpired);
+        }
+        Self::void(id, UNDO_REASON, recorded_by, conn)
+    }
+}
+
+fn check_reason(reason: &str) -> Result<&str, ContributionStructError> {
+    match reason.trim() {
+        "" => Err(ContributionStructError::MissingReason),
+        r if r.chars().count() > MAX_REASON_LEN => Err(ContributionStructError::ReasonTooLong),
+        r => Ok(r),
+    }
+}
+
+/// A contribution as listed in the panel, along with the names of what it refers to.
+/// Contributions from before recorders were tracked have no recorder.
+#[derive(Debug, Serialize)]
+pub struct ContributionEntry {
+    pub id: Uuid,
+    pub container: Option<Uuid>,
+    pub container_name: Option<String>,
+    pub amount: Money,
+    pub notes: Option<String>,
+    pub recorded_by: Option<Uuid>,
+    pub recorder_handle: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+    /// The amount first recorded, if it has been corrected since.
+    pub original_amount: Option<Money>,
+    pub correction_reason: Option<String>,
+    pub voided_at: Option<DateTime<Utc>>,
+    pub void_reason: Option<String>,
+    pub cash_count: Option<CashCount>,
+}
+
+#[derive(Debug, Default)]
+pub struct ContributionFilter {
+    pub container: Option<Uuid>,
+    pub recorded_by: Option<Uuid>,
+    /// Recorded at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Recorded before this time.
+    pub until: Option<DateTime<Utc>>,
+    pub min_amount: Option<Money>,
+    pub max_amount: Option<Money>,
+    /// Words to look for in the notes, each matching the beginning of a word.
+    pub search: Option<String>,
+}
+
+const ENTRY_QUERY: &str = "
+    SELECT c.id, c.container, k.name, c.amount, c.notes, c.recorded_by, u.handle, c.recorded_at,
+        c.original_amount, c.correction_reason, c.voided_at, c.void_reason, c.cash_count
+    FROM contributions c
+    LEFT JOIN containers k ON k.id = c.container
+    LEFT JOIN users u ON u.id = c.recorded_by
+";
+
+/// Turns words typed into the search box into an FTS5 query, quoting each of them so
+/// that FTS syntax characters are taken literally.
+fn fts_query(search: &str) -> Option<String> {
+    let terms = search
+        .split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+impl ContributionEntry {
+    pub fn get(id: &Uuid, conn: &Connection) -> Result<ContributionEntry, ContributionStructError> {
+        let mut entries = Self::read(conn, "WHERE c.id = ?1", &[&id.to_string()])?;
+        entries.pop().ok_or(ContributionStructError::NotFound)
+    }
+
+    /// The latest contribution `recorded_by` can still undo, if there is one.
+    pub fn last_undoable(
+        recorded_by: &Uuid,
+        conn: &Connection,
+    ) -> Result<Option<ContributionEntry>, ContributionStructError> {
+        let since = Utc::now().timestamp() - UNDO_WINDOW_SECS;
+        let mut entries = Self::read(
+            conn,
+            "WHERE c.recorded_by = ?1 AND c.recorded_at >= ?2 AND c.voided_at IS NULL
+             ORDER BY c.recorded_at DESC, c.id DESC LIMIT 1",
+            &[&recorded_by.to_string(), &since],
+        )?;
+        Ok(entries.pop())
+    }
+
+    /// Whether `user` may correct or void this contribution. Anyone who records
+    /// contributions can amend their own; those of others need a separate permission.
+    pub fn require_amend(&self, user: &User) -> Result<(), AuthError> {
+        match self.recorded_by == Some(user.id) {
+            true => user.require(Permission::RecordContributions),
+            false => user.require(Permission::ManageContributions),
+        }
+    }
+
+    /// A page of contributions matching `filter`, newest first, plus the total match count.
+    pub fn query(
+        conn: &Connection,
+        filter: &ContributionFilter,
+        limit: u32,
+        offset: u32,
+    ) -> Result<(Vec<ContributionEntry>, u64), ContributionStructError> {
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(container) = filter.container {
+            params.push(Box::new(container.to_string()));
+            conditions.push(format!("c.container = ?{}", params.len()));
+        }
+        if let Some(recorded_by) = filter.recorded_by {
+            params.push(Box::new(recorded_by.to_string()));
+            conditions.push(format!("c.recorded_by = ?{}", params.len()));
+        }
+        if let Some(from) = filter.from {
+            params.push(Box::new(from.timestamp()));
+            conditions.push(format!("c.recorded_at >= ?{}", params.len()));
+        }
+        if let Some(until) = filter.until {
+            params.push(Box::new(until.timestamp()));
+            conditions.push(format!("c.recorded_at < ?{}", params.len()));
+        }
+        if let Some(min) = filter.min_amount {
+            params.push(Box::new(min));
+            conditions.push(format!("c.amount >= ?{}", params.len()));
+        }
+        if let Some(max) = filter.max_amount {
+            params.push(Box::new(max));
+            conditions.push(format!("c.amount <= ?{}", params.len()));
+        }
+        if let Some(search) = filter.search.as_deref().and_then(fts_query) {
+            params.push(Box::new(search));
+            conditions.push(format!(
+                "c.id IN (SELECT id FROM contribution_notes WHERE contribution_notes MATCH ?{})",
+                params.len()
+            ));
+        }
+        let where_clause = match conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", conditions.join(" AND ")),
+        };
+
+        let total = conn
+            .prepare(&format!(
+                "SELECT COUNT(*) FROM contributions c {where_clause}"
+            ))?
+            .query_one(rusqlite::params_from_iter(params.iter()), |r| {
+                r.get::<_, u64>(0)
+            })?;
+
+        params.push(Box::new(limit));
+        params.push(Box::new(offset));
+        let clauses = format!(
+            "{where_clause} ORDER BY c.recorded_at DESC, c.id DESC LIMIT ?{} OFFSET ?{}",
+            params.len() - 1,
+            params.len()
+        );
+        let params = params.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+        Ok((Self::read(conn, &clauses, &params)?, total))
+    }
+
+    fn read(
+        conn: &Connection,
+        clauses: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<Vec<ContributionEntry>, ContributionStructError> {
+        let rows = conn
+            .prepare(&format!("{ENTRY_QUERY} {clauses}"))?
+            .query_map(params, |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, Option<String>>(1)?,
+                    r.get::<_, Option<String>>(2)?,
+                    r.get::<_, Money>(3)?,
+                    r.get::<_, Option<String>>(4)?,
+                    r.get::<_, Option<String>>(5)?,
+                    r.get::<_, Option<String>>(6)?,
+                    r.get::<_, i64>(7)?,
+                    (
+                        r.get::<_, Option<Money>>(8)?,
+                        r.get::<_, Option<String>>(9)?,
+                        r.get::<_, Option<i64>>(10)?,
+                        r.get::<_, Option<String>>(11)?,
+                        r.get::<_, Option<CashCount>>(12)?,
+                    ),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let uuid = |id: Option<String>| {
+            id.map(|id| Uuid::from_str(&id).map_err(|_| ContributionStructError::MalformedEntry))
+                .transpose()
+        };
+        rows.into_iter()
+            .map(
+                |(
+                    id,
+                    container,
+                    container_name,
+                    amount,
+                    notes,
+                    recorded_by,
+                    handle,
+                    at,
+                    (original_amount, correction_reason, voided_at, void_reason, cash_count),
+                )| {
+                    Ok(ContributionEntry {
+                        id: Uuid::from_str(&id)
+                            .map_err(|_| ContributionStructError::MalformedEntry)?,
+                        container: uuid(container)?,
+                        container_name,
+                        amount,
+                        notes,
+                        recorded_by: uuid(recorded_by)?,
+                        recorder_handle: handle,
+                        recorded_at: DateTime::from_timestamp(at, 0)
+                            .ok_or(ContributionStructError::MalformedEntry)?,
+                        original_amount,
+                        correction_reason,
+                        voided_at: voided_at
+                            .map(|at| {
+                                DateTime::from_timestamp(at, 0)
+                                    .ok_or(ContributionStructError::MalformedEntry)
+                            })
+                            .transpose()?,
+                        void_reason,
+                        cash_count,
+                    })
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{containers::Container, database::test_db};
+
+    fn add_user(handle: &str, conn: &Connection) -> Uuid {
+        let user = Uuid::now_v7();
+        conn.execute(
+            "INSERT INTO users (id, handle, passhash, role) VALUES (?1, ?2, '', 'counter')",
+            [user.to_string(), handle.to_owned()],
+        )
+        .unwrap();
+        user
+    }
+
+    fn setup(conn: &Connection) -> (Uuid, Uuid) {
+        let container = Container::create("Puszka", conn).unwrap().id;
+        (add_user("liczacy", conn), container)
+    }
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    fn counted(conn: &Connection, user: &Uuid, container: &Uuid) -> Contribution {
+        let count = CashCount::from_fields([("szt500", "2"), ("szt200", "1")]).unwrap();
+        let amount = count.total().unwrap();
+        Contribution::create(container, amount, None, user, None, Some(count), conn).unwrap()
+    }
+
+    #[test]
+    fn correcting_keeps_the_original_amount_and_drops_the_cash_count() {
+        let conn = test_db();
+        let (user, container) = setup(&conn);
+        let c = counted(&conn, &user, &container);
+        assert_eq!(c.amount, money("12"));
+
+        Contribution::correct(&c.id, money("13"), "źle policzone", &user, &conn).unwrap();
+        let entry = ContributionEntry::get(&c.id, &conn).unwrap();
+        assert_eq!(entry.amount, money("13"));
+        assert_eq!(entry.original_amount, Some(money("12")));
+        assert_eq!(entry.correction_reason.as_deref(), Some("źle policzone"));
+        assert!(entry.cash_count.is_none());
+
+        Contribution::correct(&c.id, money("14"), "jeszcze raz", &user, &conn).unwrap();
+        let entry = ContributionEntry::get(&c.id, &conn).unwrap();
+        assert_eq!(entry.original_amount, Some(money("12")));
+        assert!(matches!(
+            Contribution::correct(&c.id, money("14"), "bez zmian", &user, &conn),
+            Err(ContributionStructError::UnchangedAmount)
+        ));
+        assert!(matches!(
+            Contribution::correct(&c.id, money("15"), "  ", &user, &conn),
+            Err(ContributionStructError::MissingReason)
+        ));
+        assert!(matches!(
+            Contribution::correct(&c.id, Money::ZERO, "zero", &user, &conn),
+            Err(ContributionStructError::InvalidAmount)
+        ));
+    }
+
+    #[test]
+    fn voiding_happens_once() {
+        let conn = test_db();
+        let (user, container) = setup(&conn);
+        let c = counted(&conn, &user, &container);
+
+        Contribution::void(&c.id, "pomyłka", &user, &conn).unwrap();
+        let entry = ContributionEntry::get(&c.id, &conn).unwrap();
+        assert!(entry.voided_at.is_some());
+        assert_eq!(entry.void_reason.as_deref(), Some("pomyłka"));
+        assert_eq!(entry.amount, money("12"));
+        assert!(matches!(
+            Contribution::void(&c.id, "znowu", &user, &conn),
+            Err(ContributionStructError::Voided)
+        ));
+        assert!(matches!(
+            Contribution::void(&Uuid::now_v7(), "nie ma", &user, &conn),
+            Err(ContributionStructError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn voided_contributions_cant_be_corrected() {
+        let conn = test_db();
+        let (user, container) = setup(&conn);
+        let c = counted(&conn, &user, &container);
+
+        Contribution::void(&c.id, "pomyłka", &user, &conn).unwrap();
+        assert!(matches!(
+            Contribution::correct(&c.id, money("20"), "poprawka", &user, &conn),
+            Err(ContributionStructError::Voided)
+        ));
+        let entry = ContributionEntry::get(&c.id, &conn).unwrap();
+        assert_eq!(entry.amount, money("12"));
+        assert!(entry.original_amount.is_none());
+        assert!(entry.cash_count.is_some());
+    }
+
+    #[test]
+    fn idempotency_keys_find_what_they_recorded() {
+        let conn = test_db();
+        let (user, container) = setup(&conn);
+        let key = Uuid::now_v7();
+        let c = Contribution::create(&container, money("5"), None, &user, Some(&key), None, &conn)
+            .unwrap();
+
+        let found = Contribution::get_by_idempotency_key(&key, &user, &conn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, c.id);
+        assert_eq!(found.amount, money("5"));
+        assert!(
+            Contribution::get_by_idempotency_key(&Uuid::now_v7(), &user, &conn)
+                .unwrap()
+                .is_none()
+        );
+        let someone_else = add_user("ktos", &conn);
+        assert!(matches!(
+            Contribution::get_by_idempotency_key(&key, &someone_else, &conn),
+            Err(ContributionStructError::ForeignKey)
+        ));
+        // and a key can't be used for a second contribution
+        assert!(
+            Contribution::create(&container, money("5"), None, &user, Some(&key), None, &conn)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn undo_is_only_for_the_recorder_within_the_window() {
+        let conn = test_db();
+        let (user, container) = setup(&conn);
+        let someone_else = add_user("ktos", &conn);
+        let create = || {
+            Contribution::create(&container, money("5"), None, &user, None, None, &conn)
+                .unwrap()
+                .id
+        };
+
+        let id = create();
+        assert!(matches!(
+            Contribution::undo(&id, &someone_else, &conn),
+            Err(ContributionStructError::NotUndoable)
+        ));
+        Contribution::undo(&id, &user, &conn).unwrap();
+        let entry = ContributionEntry::get(&id, &conn).unwrap();
+        assert!(entry.voided_at.is_some());
+        assert_eq!(entry.void_reason.as_deref(), Some(UNDO_REASON));
+        assert!(matches!(
+            Contribution::undo(&id, &user, &conn),
+            Err(ContributionStructError::Voided)
+        ));
+
+        let id = create();
+        let recorded_at = Utc::now().timestamp() - UNDO_WINDOW_SECS - 1;
+        conn.execute(
+            "UPDATE contributions SET recorded_at = ?2 WHERE id = ?1",
+            (id.to_string(), recorded_at),
+        )
+        .unwrap();
+        assert!(matches!(
+            Contribution::undo(&id, &user, &conn),
+            Err(ContributionStructError::UndoExpired)
+        ));
+        assert!(
+            ContributionEntry::get(&id, &conn)
+                .unwrap()
+                .voided_at
+                .is_none()
+        );
+    }
+}
@@ -1,8 +1,90 @@
+use std::{error::Error, fs, io::Write, sync::OnceLock};
+
+use blake2::{
+    Blake2sMac256,
+    digest::{KeyInit, Mac},
+};
 use rand08::{Rng, SeedableRng, rngs::StdRng};
 
+use crate::database::data_dir;
+
+const TOKEN_KEY_LEN: usize = 32;
+static TOKEN_KEY: OnceLock<[u8; TOKEN_KEY_LEN]> = OnceLock::new();
+
+fn random_token(bytes: &mut [u8]) -> String {
+    StdRng::from_entropy().fill(bytes);
+    base32::encode(base32::Alphabet::Crockford, bytes)
+}
+
 // from: jakubmanczak/quote-engine.git
 pub fn generate_short_token() -> String {
-    let mut bytes = [0u8; 8];
+    random_token(&mut [0u8; 8])
+}
+
+/// A 256-bit token, for anything that grants access on its own (sessions, API tokens).
+pub fn generate_long_token() -> String {
+    random_token(&mut [0u8; 32])
+}
+
+/// A 160-bit TOTP secret in RFC 4648 base32, the alphabet authenticator apps expect.
+pub fn generate_totp_secret() -> String {
+    let mut bytes = [0u8; 20];
     StdRng::from_entropy().fill(&mut bytes);
-    base32::encode(base32::Alphabet::Crockford, &bytes)
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes)
+}
+
+/// Loads the key for [`hash_token`] from `TOKEN_KEY`, or from `token.key` next to the
+/// database, generating the file on first start. Keeping the key out of the database
+/// means a leaked `db.db` or backup doesn't give away any usable tokens.
+pub fn init_token_key() -> Result<(), Box<dyn Error>> {
+    let encoded = match std::env::var("TOKEN_KEY") {
+        Ok(k) => k,
+        Err(std::env::VarError::NotPresent) => {
+            let path = data_dir().join("token.key");
+            match fs::read_to_string(&path) {
+                Ok(k) => k,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    let key = random_token(&mut [0u8; TOKEN_KEY_LEN]);
+                    let mut options = fs::OpenOptions::new();
+                    options.write(true).create_new(true);
+                    #[cfg(unix)]
+                    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                    options.open(&path)?.write_all(key.as_bytes())?;
+                    println!("generated a new token key at {}", path.display());
+                    key
+                }
+                Err(e) => return Err(e)?,
+            }
+        }
+        Err(e) => return Err(e)?,
+    };
+    let key = base32::decode(base32::Alphabet::Crockford, encoded.trim())
+        .and_then(|k| <[u8; TOKEN_KEY_LEN]>::try_from(k).ok())
+        .ok_or("token key must be 32 bytes of Crockford base32")?;
+    _ = TOKEN_KEY.set(key);
+    Ok(())
+}
+
+/// Keyed hash of a secret token, as stored in the database in place of the token itself.
+pub fn hash_token(token: &str) -> String {
+    let key = TOKEN_KEY.get().expect("token key is loaded at startup");
+    let mut mac = <Blake2sMac256 as KeyInit>::new_from_slice(key).expect("key fits BLAKE2s");
+    Mac::update(&mut mac, token.as_bytes());
+    base32::encode(base32::Alphabet::Crockford, &mac.finalize().into_bytes())
+}
+
+/// Compares two strings in time that depends only on their length, for checking
+/// tokens and signatures without leaking how much of them was right.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Loads a fixed key for [`hash_token`], for tests.
+#[cfg(test)]
+pub fn init_test_token_key() {
+    _ = TOKEN_KEY.set([7; TOKEN_KEY_LEN]);
 }
@@ -1,25 +1,73 @@
+use chrono::Utc;
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, OptionalExtension};
-use std::error::Error;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use uuid::Uuid;
 
-use crate::{crypto::generate_short_token, users::pwd::hash_password};
+use crate::{
+    crypto::generate_short_token,
+    users::{pwd::hash_password, roles::Role},
+};
 
-const SCHEMA: &str = include_str!("./schema.sql");
+/// Schema migrations in order of application. A database's `user_version`
+/// is the number of migrations already applied to it; append new ones, never
+/// edit the ones that have shipped.
+const MIGRATIONS: &[&str] = &[
+    include_str!("./migrations/0001_initial.sql"),
+    include_str!("./migrations/0002_roles_archive_audit.sql"),
+    include_str!("./migrations/0003_session_metadata.sql"),
+    include_str!("./migrations/0004_jobs.sql"),
+    include_str!("./migrations/0005_hashed_session_tokens.sql"),
+    include_str!("./migrations/0006_api_tokens.sql"),
+    include_str!("./migrations/0007_login_attempts.sql"),
+    include_str!("./migrations/0008_totp.sql"),
+    include_str!("./migrations/0009_config.sql"),
+    include_str!("./migrations/0010_contribution_search.sql"),
+    include_str!("./migrations/0011_contribution_corrections.sql"),
+    include_str!("./migrations/0012_contribution_idempotency.sql"),
+    include_str!("./migrations/0013_cash_count.sql"),
+];
 
-pub fn open_db() -> Result<Connection, rusqlite::Error> {
-    let conn = Connection::open(std::env::var("DB_PATH").unwrap_or(String::from("./db.db")))?;
-    Ok(conn)
+/// How long a connection waits on a lock held by another writer before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_SIZE: u32 = 8;
+
+/// How many of the newest backups [`backup`] keeps around.
+const BACKUPS_KEPT: usize = 14;
+
+fn db_path() -> String {
+    std::env::var("DB_PATH").unwrap_or(String::from("./db.db"))
 }
 
-pub fn db_check() -> Result<(), Box<dyn Error>> {
-    let conn = open_db()?;
-    if conn.table_exists(None, "logs")? {
-        println!("yippee good database!")
-    } else {
-        println!("applying ts");
-        conn.execute_batch(SCHEMA)?;
-        println!("applied schema to fresh database yayy!");
+/// Directory holding the database, where backups and reports are written too,
+/// so that they end up on the same volume.
+pub fn data_dir() -> PathBuf {
+    match Path::new(&db_path()).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+        _ => PathBuf::from("."),
     }
+}
+
+pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
+
+/// Opens the connection pool shared by all handlers. Every connection runs in
+/// WAL mode, so readers (the dashboard) don't block the counters' writes.
+pub fn open_pool() -> Result<DbPool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(db_path()).with_init(|conn| {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+    });
+    r2d2::Pool::builder().max_size(POOL_SIZE).build(manager)
+}
+
+pub fn db_check(pool: &DbPool) -> Result<(), Box<dyn Error>> {
+    let mut conn = pool.get()?;
+    migrate(&mut conn)?;
 
     if conn
         .prepare("SELECT * FROM users WHERE id = ?1")?
@@ -29,8 +77,13 @@ pub fn db_check() -> Result<(), Box<dyn Error>> {
     {
         let pw = generate_short_token();
         let hash = hash_password(&pw)?;
-        conn.prepare("INSERT INTO users VALUES (?1, ?2, ?3)")?
-            .insert([Uuid::max().to_string(), "admin".to_owned(), hash])?;
+        conn.prepare("INSERT INTO users (id, handle, passhash, role) VALUES (?1, ?2, ?3, ?4)")?
+            .insert([
+                Uuid::max().to_string(),
+                "admin".to_owned(),
+                hash,
+                Role::Infradmin.as_str().to_owned(),
+            ])?;
         println!("default infradmin account was made.\nhandle: admin\npasswd: {pw}\n");
         println!("please change the password for increased safety.");
     }
@@ -50,3 +103,63 @@ pub fn db_check() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Brings the database up to the latest schema version in a single transaction.
+/// Refuses to touch a database written by a newer build.
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "database schema version {version} is newer than this build supports ({}), refusing to start",
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    if version == MIGRATIONS.len() {
+        println!("yippee good database! (schema version {version})");
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        println!("applying migration {}", i + 1);
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    println!(
+        "migrated database from schema version {version} to {} yayy!",
+        MIGRATIONS.len()
+    );
+    Ok(())
+}
+
+/// A fresh in-memory database on the latest schema, for tests.
+#[cfg(test)]
+pub fn test_db() -> Connection {
+    let mut conn = Connection::open_in_memory().expect("in-memory database");
+    migrate(&mut conn).expect("migrations apply");
+    conn.execute("INSERT INTO config DEFAULT VALUES", [])
+        .expect("config row");
+    conn
+}
+
+/// Writes a consistent copy of the database to `backups/` next to it, dropping the
+/// oldest backups beyond [`BACKUPS_KEPT`]. Returns the new backup's path.
+pub fn backup(conn: &Connection) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let dir = data_dir().join("backups");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("db-{}.db", Utc::now().format("%Y%m%d-%H%M%S")));
+    conn.execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+
+    let mut backups = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "db"))
+        .collect::<Vec<_>>();
+    backups.sort(); // timestamped names sort chronologically
+    let excess = backups.len().saturating_sub(BACKUPS_KEPT);
+    for old in &backups[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(path)
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::{
+    config::{self, DEFAULT_NAME},
+    html::error_page,
+    state::{AppState, DbError},
+    users::auth::AuthError,
+};
+
+pub const SERVER_ERROR: &str = "Błąd serwera. Skontaktuj się z webmasterem.";
+
+/// What a page or API handler can fail with. Rendered by [`render_errors`] as a styled
+/// page for the panel and as JSON under `/api/`.
+#[derive(thiserror::Error, Debug)]
+pub enum AppError {
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Db(#[from] DbError),
+    #[error("Failed to execute SQL: {0}")]
+    Sql(#[from] rusqlite::Error),
+    #[error("Failed to read {0} data: {1}")]
+    Read(&'static str, Box<dyn Error + Send + Sync>),
+    #[error("Authentication required")]
+    Unauthenticated,
+    /// The request was understood but can't be carried out; the message says why.
+    #[error("{0}")]
+    Rejected(&'static str),
+}
+impl AppError {
+    /// For `map_err` on a failed read of some kind of data, e.g. `AppError::read("user")`.
+    pub fn read<E: Into<Box<dyn Error + Send + Sync>>>(
+        what: &'static str,
+    ) -> impl FnOnce(E) -> Self {
+        move |e| AppError::Read(what, e.into())
+    }
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Auth(e) => e.status_code(),
+            AppError::Db(_) | AppError::Sql(_) | AppError::Read(..) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AppError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            AppError::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+    pub fn msg(&self) -> &'static str {
+        match self {
+            AppError::Auth(e) => e.msg(),
+            AppError::Db(_) | AppError::Sql(_) | AppError::Read(..) => SERVER_ERROR,
+            AppError::Unauthenticated => "Zaloguj się, aby kontynuować.",
+            AppError::Rejected(msg) => msg,
+        }
+    }
+}
+
+/// Marks a response as a failure for [`render_errors`] to dress up.
+#[derive(Clone)]
+struct ErrorMessage(&'static str);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            eprintln!("{self}");
+        }
+        let mut response = (status, self.msg()).into_response();
+        response.extensions_mut().insert(ErrorMessage(self.msg()));
+        response
+    }
+}
+
+/// Middleware rendering [`AppError`]s the way a route's clients expect them: as
+/// `{"error": "..."}` under `/api/`, as an error page everywhere else.
+pub async fn render_errors(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let api = request.uri().path().starts_with("/api/");
+    let response = next.run(request).await;
+    let Some(ErrorMessage(msg)) = response.extensions().get::<ErrorMessage>().cloned() else {
+        return response;
+    };
+    let status = response.status();
+    match api {
+        true => (status, Json(json!({ "error": msg }))).into_response(),
+        false => {
+            let name = state
+                .with_db(config::competition_name)
+                .await
+                .unwrap_or_else(|_| DEFAULT_NAME.to_owned());
+            (status, error_page(&name, status, msg)).into_response()
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use axum::{
+    extract::Request,
+    http::{
+        HeaderMap, HeaderValue,
+        header::{COOKIE, SET_COOKIE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::prelude::*;
+
+use crate::crypto::{constant_time_eq, hash_token};
+
+const COOKIE_NAME: &str = "wpikflash";
+const COOKIE_CLEAR: &str = concat!("wpikflash", "=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0");
+/// A notice is meant for the very next page, so the cookie doesn't need to live long.
+const COOKIE_MAX_AGE_SECS: u32 = 60;
+
+/// A one-shot notice shown on the page a form redirects to. It travels in a signed
+/// cookie, so it can't be planted through a link the way a query string could.
+pub enum Flash {
+    Error(String),
+    Success(String),
+}
+
+impl Flash {
+    pub fn error(msg: impl Into<String>) -> Flash {
+        Flash::Error(msg.into())
+    }
+    pub fn success(msg: impl Into<String>) -> Flash {
+        Flash::Success(msg.into())
+    }
+    /// Redirects to `to`, showing this notice there.
+    pub fn redirect(self, to: impl Into<String>) -> FlashRedirect {
+        FlashRedirect {
+            to: to.into(),
+            flash: Some(self),
+        }
+    }
+    /// The notice in the request's flash cookie, if it carries a genuine one.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Flash> {
+        let value = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .find_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))?;
+        let (payload, signature) = value.rsplit_once('.')?;
+        if !constant_time_eq(&hash_token(&format!("{COOKIE_NAME}:{payload}")), signature) {
+            return None;
+        }
+        let (kind, msg) = payload.split_once('.')?;
+        let msg = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(msg).ok()?).ok()?;
+        match kind {
+            "error" => Some(Flash::Error(msg)),
+            "success" => Some(Flash::Success(msg)),
+            _ => None,
+        }
+    }
+    fn cookie(&self) -> String {
+        let (kind, msg) = match self {
+            Flash::Error(msg) => ("error", msg),
+            Flash::Success(msg) => ("success", msg),
+        };
+        let payload = format!("{kind}.{}", BASE64_URL_SAFE_NO_PAD.encode(msg));
+        let signature = hash_token(&format!("{COOKIE_NAME}:{payload}"));
+        format!(
+            "{COOKIE_NAME}={payload}.{signature}; Path=/; HttpOnly; SameSite=Lax; Max-Age={COOKIE_MAX_AGE_SECS}"
+        )
+    }
+}
+
+/// A redirect, optionally carrying a [`Flash`] notice for the page it leads to.
+pub struct FlashRedirect {
+    to: String,
+    flash: Option<Flash>,
+}
+
+impl FlashRedirect {
+    /// A redirect without a notice.
+    pub fn to(to: impl Into<String>) -> FlashRedirect {
+        FlashRedirect {
+            to: to.into(),
+            flash: None,
+        }
+    }
+}
+
+impl IntoResponse for FlashRedirect {
+    fn into_response(self) -> Response {
+        let mut response = Redirect::to(&self.to).into_response();
+        if let Some(flash) = self.flash
+            && let Ok(cookie) = HeaderValue::from_str(&flash.cookie())
+        {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+        response
+    }
+}
+
+/// Middleware removing the flash cookie once a response has had the chance to show it,
+/// unless that response leaves a new notice behind.
+pub async fn clear_flash(request: Request, next: Next) -> Response {
+    let had_flash = Flash::from_headers(request.headers()).is_some();
+    let mut response = next.run(request).await;
+    let sets_flash = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|c| c.as_bytes().starts_with(COOKIE_NAME.as_bytes()));
+    if had_flash && !sets_flash {
+        response
+            .headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_static(COOKIE_CLEAR));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::init_test_token_key;
+
+    /// Headers of a request sending back the cookie value `value`.
+    fn sent(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let cookie = format!("theme=dark; {COOKIE_NAME}={value}");
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        headers
+    }
+
+    /// The value of the flash cookie set for `flash`.
+    fn cookie_value(flash: Flash) -> String {
+        let cookie = flash.cookie();
+        let (pair, _) = cookie.split_once(';').unwrap();
+        pair.strip_prefix(&format!("{COOKIE_NAME}="))
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn notices_survive_the_round_trip() {
+        init_test_token_key();
+        let value = cookie_value(Flash::error("Nie udało się; spróbuj = jeszcze raz."));
+        match Flash::from_headers(&sent(&value)) {
+            Some(Flash::Error(msg)) => assert_eq!(msg, "Nie udało się; spróbuj = jeszcze raz."),
+            _ => panic!("error notice wasn't read back"),
+        }
+        let value = cookie_value(Flash::success("Zapisano."));
+        assert!(matches!(
+            Flash::from_headers(&sent(&value)),
+            Some(Flash::Success(msg)) if msg == "Zapisano."
+        ));
+        assert!(Flash::from_headers(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn tampered_notices_are_ignored() {
+        init_test_token_key();
+        let value = cookie_value(Flash::error("Prawdziwy błąd."));
+        let (payload, signature) = value.rsplit_once('.').unwrap();
+        let (_, msg) = payload.split_once('.').unwrap();
+
+        // the same message passed off as a success
+        assert!(Flash::from_headers(&sent(&format!("success.{msg}.{signature}"))).is_none());
+        // a message of the attacker's choosing
+        let planted = BASE64_URL_SAFE_NO_PAD.encode("Wejdź na zly.example");
+        assert!(Flash::from_headers(&sent(&format!("error.{planted}.{signature}"))).is_none());
+        // a broken or missing signature
+        let mut forged = signature.to_owned();
+        forged.replace_range(..1, if forged.starts_with('A') { "B" } else { "A" });
+        assert!(Flash::from_headers(&sent(&format!("{payload}.{forged}"))).is_none());
+        assert!(Flash::from_headers(&sent(payload)).is_none());
+        assert!(Flash::from_headers(&sent(&format!("{payload}."))).is_none());
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, Utc};
+use maud::{Markup, html};
+use uuid::Uuid;
+
+use crate::{
+    money::group_thousands,
+    stats::{ContainerTotal, TimelinePoint},
+};
+
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 300.0;
+const MARGIN_LEFT: f64 = 80.0;
+const MARGIN_RIGHT: f64 = 12.0;
+const MARGIN_TOP: f64 = 24.0;
+const MARGIN_BOTTOM: f64 = 36.0;
+
+const AXIS_COLOR: &str = "#525252";
+const LABEL_COLOR: &str = "#d4d4d4";
+const LEAD_COLOR: &str = "#fafafa";
+const PALETTE: &[&str] = &[
+    "#f59e0b", "#38bdf8", "#a3e635", "#f472b6", "#c084fc", "#2dd4bf", "#fb7185", "#facc15",
+];
+
+/// Assigns each container a palette colour by creation order, so a container
+/// keeps its colour regardless of its current place in the ranking.
+pub fn container_colors(totals: &[ContainerTotal]) -> HashMap<Uuid, &'static str> {
+    let mut ids = totals.iter().map(|t| t.id).collect::<Vec<_>>();
+    ids.sort(); // UUIDv7 ids sort chronologically
+    ids.into_iter()
+        .enumerate()
+        .map(|(i, id)| (id, PALETTE[i % PALETTE.len()]))
+        .collect()
+}
+
+/// Vertical scale shared by both charts: a round step and the axis maximum, in grosze.
+struct Scale {
+    step: i64,
+    max: i64,
+}
+
+impl Scale {
+    /// Picks a step of 1, 2 or 5 times a power of ten (in whole złoty) giving at most five ticks.
+    fn for_max(max: i64) -> Scale {
+        let max_zl = (max.max(1) + 99) / 100;
+        let rough = (max_zl + 4) / 5;
+        let mut magnitude = 1;
+        while magnitude * 10 < rough {
+            magnitude *= 10;
+        }
+        let step_zl = [1, 2, 5, 10]
+            .into_iter()
+            .map(|m| m * magnitude)
+            .find(|s| *s >= rough)
+            .unwrap_or(10 * magnitude);
+        let step = step_zl * 100;
+        Scale {
+            step,
+            max: (max.max(1) + step - 1) / step * step,
+        }
+    }
+    fn y(&self, value: i64) -> f64 {
+        let plot = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        HEIGHT - MARGIN_BOTTOM - plot * value as f64 / self.max as f64
+    }
+    fn ticks(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..=self.max / self.step).map(|i| i * self.step)
+    }
+}
+
+fn tick_label(grosze: i64) -> String {
+    format!("{}\u{a0}zł", group_thousands(grosze.unsigned_abs() / 100))
+}
+
+// SVG shapes are closed with `{}` rather than `;`: inline SVG in HTML has no void
+// elements, so a bare `<line>` would swallow everything rendered after it.
+fn y_axis(scale: &Scale) -> Markup {
+    html! {
+        @for tick in scale.ticks() {
+            line x1=(MARGIN_LEFT) x2=(WIDTH - MARGIN_RIGHT) y1=(scale.y(tick)) y2=(scale.y(tick))
+                stroke=(AXIS_COLOR) stroke-width="1" {}
+            text x=(MARGIN_LEFT - 8.0) y=(scale.y(tick) + 4.0) text-anchor="end"
+                font-size="12" fill=(LABEL_COLOR) { (tick_label(tick)) }
+        }
+    }
+}
+
+/// Bar chart of the total collected in every container.
+pub fn totals_bar_chart(totals: &[ContainerTotal], colors: &HashMap<Uuid, &str>) -> Markup {
+    let scale = Scale::for_max(totals.iter().map(|t| t.total.grosze()).max().unwrap_or(0));
+    let slot = (WIDTH - MARGIN_LEFT - MARGIN_RIGHT) / totals.len().max(1) as f64;
+    let bar = slot * 0.6;
+
+    html! {
+        svg.w-full.h-full viewBox=(format!("0 0 {WIDTH} {HEIGHT}")) xmlns="http://www.w3.org/2000/svg"
+            role="img" aria-label="Suma datków w każdym pojemniku" {
+            (y_axis(&scale))
+            @for (i, t) in totals.iter().enumerate() {
+                @let x = MARGIN_LEFT + slot * i as f64 + (slot - bar) / 2.0;
+                @let y = scale.y(t.total.grosze());
+                rect x=(x) y=(y) width=(bar) height=(HEIGHT - MARGIN_BOTTOM - y) rx="2"
+                    fill=(colors.get(&t.id).copied().unwrap_or(LABEL_COLOR)) {}
+                text x=(x + bar / 2.0) y=(y - 6.0) text-anchor="middle" font-size="13" fill=(LABEL_COLOR) {
+                    (t.total)
+                }
+                text x=(x + bar / 2.0) y=(HEIGHT - MARGIN_BOTTOM + 18.0) text-anchor="middle"
+                    font-size="13" fill=(LABEL_COLOR) { (t.name) }
+            }
+        }
+    }
+}
+
+/// Line chart of each container's running total over time, with the leader's
+/// margin over the runner-up drawn as a dashed line.
+pub fn lead_line_chart(timeline: &[TimelinePoint], colors: &HashMap<Uuid, &str>) -> Markup {
+    let (Some(first), Some(last)) = (timeline.first(), timeline.last()) else {
+        return html! { p { "Brak datków." } };
+    };
+    let start = first.at.timestamp();
+    let span = (last.at.timestamp() - start).max(1) as f64;
+
+    let mut running = colors
+        .keys()
+        .map(|id| (*id, 0))
+        .collect::<HashMap<Uuid, i64>>();
+    let mut series = colors
+        .keys()
+        .map(|id| (*id, vec![(start, 0)]))
+        .collect::<HashMap<Uuid, Vec<(i64, i64)>>>();
+    let mut lead = vec![(start, 0)];
+    for p in timeline {
+        let at = p.at.timestamp();
+        let total = running.entry(p.container).or_insert(0);
+        let line = series
+            .entry(p.container)
+            .or_insert_with(|| vec![(start, 0)]);
+        line.push((at, *total));
+        *total += p.amount.grosze();
+        line.push((at, *total));
+
+        let mut standings = running.values().copied().collect::<Vec<_>>();
+        standings.sort_unstable_by(|a, b| b.cmp(a));
+        let margin = standings[0] - standings.get(1).copied().unwrap_or(0);
+        lead.push((at, lead.last().map(|l| l.1).unwrap_or(0)));
+        lead.push((at, margin));
+    }
+    let end = last.at.timestamp();
+    for line in series.values_mut().chain(std::iter::once(&mut lead)) {
+        let value = line.last().map(|l| l.1).unwrap_or(0);
+        line.push((end, value));
+    }
+
+    let scale = Scale::for_max(running.values().copied().max().unwrap_or(0));
+    let x =
+        |at: i64| MARGIN_LEFT + (WIDTH - MARGIN_LEFT - MARGIN_RIGHT) * (at - start) as f64 / span;
+    let points = |line: &[(i64, i64)]| {
+        line.iter()
+            .map(|(at, v)| format!("{:.1},{:.1}", x(*at), scale.y(*v)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut ids = series.keys().copied().collect::<Vec<_>>();
+    ids.sort();
+
+    html! {
+        svg.w-full.h-full viewBox=(format!("0 0 {WIDTH} {HEIGHT}")) xmlns="http://www.w3.org/2000/svg"
+            role="img" aria-label="Sumy w pojemnikach i przewaga lidera w czasie" {
+            (y_axis(&scale))
+            text x=(MARGIN_LEFT) y=(HEIGHT - 10.0) text-anchor="start" font-size="12" fill=(LABEL_COLOR) {
+                (time_label(&first.at, &last.at, &first.at))
+            }
+            text x=(WIDTH - MARGIN_RIGHT) y=(HEIGHT - 10.0) text-anchor="end" font-size="12" fill=(LABEL_COLOR) {
+                (time_label(&first.at, &last.at, &last.at))
+            }
+            @for id in &ids {
+                polyline points=(points(&series[id])) fill="none" stroke-width="2"
+                    stroke=(colors.get(id).copied().unwrap_or(LABEL_COLOR)) {}
+            }
+            polyline points=(points(&lead)) fill="none" stroke=(LEAD_COLOR) stroke-width="2"
+                stroke-dasharray="6 4" {}
+            line x1=(MARGIN_LEFT + 4.0) x2=(MARGIN_LEFT + 28.0) y1="10" y2="10"
+                stroke=(LEAD_COLOR) stroke-width="2" stroke-dasharray="6 4" {}
+            text x=(MARGIN_LEFT + 34.0) y="14" font-size="12" fill=(LABEL_COLOR) { "przewaga lidera" }
+        }
+    }
+}
+
+/// Formats a time axis label in local time, adding the date if the chart spans several days.
+fn time_label(start: &DateTime<Utc>, end: &DateTime<Utc>, at: &DateTime<Utc>) -> String {
+    let at = at.with_timezone(&Local);
+    match start.with_timezone(&Local).date_naive() == end.with_timezone(&Local).date_naive() {
+        true => at.format("%H:%M").to_string(),
+        false => at.format("%d.%m %H:%M").to_string(),
+    }
+}
@@ -1,92 +1,110 @@
 use axum::{
-    http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Redirect, Response},
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
 };
 use maud::{Markup, html};
+use rusqlite::Connection;
 
 use crate::{
-    database::open_db,
-    html::{controls::controls_user_witaj, head},
-    users::User,
+    config::Config,
+    containers::Container,
+    error::AppError,
+    flash::Flash,
+    html::{
+        controls::{controls_notices, controls_user_witaj, panel_user},
+        head,
+    },
+    state::AppState,
+    users::{csrf::CsrfToken, roles::Permission},
 };
 
-pub async fn controls_containers(headers: HeaderMap) -> Response {
-    let conn = match open_db() {
-        Ok(c) => c,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Couldn't open database").into_response();
-        }
-    };
-    let user = match User::authenticate(&headers, &conn) {
-        Ok(Some(u)) => u,
-        Ok(None) => return Redirect::to("/panel").into_response(),
-        Err(e) => return (e.status_code(), e.msg().to_string()).into_response(),
-    };
-    let containers = match conn
-        .prepare("SELECT id, name FROM containers")
-        .unwrap()
-        .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))
-        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
-    {
-        Ok(c) => c,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read container data",
-            )
-                .into_response();
-        }
+pub async fn controls_containers(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state
+        .respond(move |conn| containers_page(conn, &headers))
+        .await
+}
+
+fn containers_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
     };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    let containers = Container::get_all(conn).map_err(AppError::read("container"))?;
 
-    (html! {
-        (head("Zbiorywalizacja WPiK"))
+    Ok(html! {
+        (head(&config.competition_name))
         body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
         .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
-            a href="/" { p { "Zbiorywalizacja WPiK" } }
+            a href="/" { p { (config.competition_name) } }
             a href="/panel" { p { "Panel kontrolny" } }
         }
-        (controls_user_witaj(user))
-        (containers_list(containers))
-        (new_container())
-    })
-    .into_response()
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        (containers_list(containers, user.can(Permission::ManageContainers), csrf))
+        @if user.can(Permission::ManageContainers) {
+            (new_container(csrf))
+        }
+    }
+    .into_response())
 }
 
-fn containers_list(containers: Vec<(String, String)>) -> Markup {
+fn containers_list(containers: Vec<Container>, manage: bool, csrf: &CsrfToken) -> Markup {
     html! {
         .mx-auto.max-w-3xl.p-4 {
             p.font-serif.text-xl.ml-1 {"Pojemniki"}
-            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @if containers.is_empty() {
+                    p.text-center { "Brak pojemników." }
+                }
                 @for container in containers {
-                    p {(container.1) " (" (container.0) ")" }
+                    .flex.flex-col.gap-1 {
+                        p {
+                            (container.name)
+                            @if container.archived {
+                                span.text-neutral-500 { " (zarchiwizowany)" }
+                            }
+                        }
+                        @if manage {
+                            .flex.flex-wrap.gap-2 {
+                                form.flex.gap-2.flex-1 method="post" action=(format!("/panel/pojemniki/{}/nazwa", container.id)) {
+                                    (csrf)
+                                    input name="contname" value=(container.name) required
+                                        .flex-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Zmień nazwę" }
+                                }
+                                form method="post" action=(format!("/panel/pojemniki/{}/archiwum", container.id)) {
+                                    (csrf)
+                                    input type="hidden" name="archived" value=(!container.archived);
+                                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer {
+                                        @if container.archived { "Przywróć" } @else { "Archiwizuj" }
+                                    }
+                                }
+                                form method="post" action=(format!("/panel/pojemniki/{}/usun", container.id)) {
+                                    (csrf)
+                                    button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer { "Usuń" }
+                                }
+                            }
+                        }
+                    }
                 }
             }
         }
     }
 }
 
-fn new_container() -> Markup {
+fn new_container(csrf: &CsrfToken) -> Markup {
     html! {
         .mx-auto.max-w-3xl.p-4 {
             p.font-serif.text-xl.ml-1 { "Nowy pojemnik" }
             .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
-                form .flex.flex-col.gap-1 {
+                form .flex.flex-col.gap-1 method="post" action="/panel/pojemniki" {
+                    (csrf)
                     label for="contname" .mr-4{"Nazwa pojemnika"}
-                    input name="contname" .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    input name="contname" id="contname" required .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
                     button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto {"Utwórz pojemnik"}
-                    // label for="contrbank" .mr-4 { "Pojemnik" }
-                    // select name="contrbank" id="contrbank" .mb-3.p-2.border.border-neutral-600.rounded.bg-neutral-900 {
-                    //     @for (id, name) in containers {
-                    //         option value=(id) { (name) }
-                    //     }
-                    // }
-                    // label for="contramt" .mr-4{"Wielkość datku " span.text-neutral-500{"(w zł)"} }
-                    // input name="contramt" type="number" step="0.01" min="0" required
-                    //     value=(format!("{:.2}", default_contramt as f64 / 100.0))
-                    //     .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
-                    // label for "contrnote" .mr-4{"Notatka do datku " span.text-neutral-500{"(opcjonalnie)"}}
-                    // input name="contrnote" type="text" .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
-                    // button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Odnotuj datek" }
                 }
             }
         }
@@ -0,0 +1,524 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Days, Local, NaiveDate, Utc};
+use maud::{Markup, PreEscaped, html};
+use rusqlite::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, LogFilter},
+    cash::{self, CashCount, DENOMINATIONS, SMALLEST_NOTE, denomination_label},
+    config::Config,
+    containers::Container,
+    contributions::{ContributionEntry, ContributionFilter, ContributionStructError},
+    error::AppError,
+    flash::Flash,
+    html::{
+        JS_CASH_TOTAL,
+        controls::{controls_notices, controls_user_witaj, logs::log_table, panel_user},
+        head, local_time,
+    },
+    money::Money,
+    state::AppState,
+    users::{User, csrf::CsrfToken, roles::Permission},
+};
+
+const PAGE_SIZE: u32 = 50;
+/// More than a contribution should ever collect, but still a bound on the page.
+const HISTORY_LIMIT: u32 = 200;
+
+#[derive(Deserialize)]
+pub struct ContributionsQuery {
+    pojemnik: Option<String>,
+    konto: Option<String>,
+    od: Option<String>,
+    #[serde(rename = "do")]
+    do_: Option<String>,
+    min: Option<String>,
+    max: Option<String>,
+    szukaj: Option<String>,
+    strona: Option<String>,
+}
+
+pub async fn controls_contributions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ContributionsQuery>,
+) -> Response {
+    state
+        .respond(move |conn| contributions_page(conn, &headers, query))
+        .await
+}
+
+fn local_day(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+/// Start of a day in the server's time zone, as typed into a date filter.
+fn day_start(date: NaiveDate) -> Option<DateTime<Utc>> {
+    date.and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|d| d.to_utc())
+}
+
+fn contributions_page(
+    conn: &Connection,
+    headers: &HeaderMap,
+    query: ContributionsQuery,
+) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if let Err(e) = user.require(Permission::ViewContributions) {
+        return Ok(Flash::error(e.msg()).redirect("/panel").into_response());
+    }
+
+    // unparseable filters are dropped, and the form shows what was actually applied
+    let non_empty = |v: Option<String>| v.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+    let from = non_empty(query.od).as_deref().and_then(local_day);
+    let to = non_empty(query.do_).as_deref().and_then(local_day);
+    let filter = ContributionFilter {
+        container: non_empty(query.pojemnik).and_then(|c| Uuid::from_str(&c).ok()),
+        recorded_by: non_empty(query.konto).and_then(|a| Uuid::from_str(&a).ok()),
+        from: from.and_then(day_start),
+        until: to
+            .and_then(|d| d.checked_add_days(Days::new(1)))
+            .and_then(day_start),
+        min_amount: non_empty(query.min).and_then(|m| m.parse::<Money>().ok()),
+        max_amount: non_empty(query.max).and_then(|m| m.parse::<Money>().ok()),
+        search: non_empty(query.szukaj),
+    };
+    let page = non_empty(query.strona)
+        .and_then(|p| p.parse::<u32>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let (entries, total) = ContributionEntry::query(
+        conn,
+        &filter,
+        PAGE_SIZE,
+        (page - 1).saturating_mul(PAGE_SIZE),
+    )
+    .map_err(AppError::read("contribution"))?;
+    let containers = Container::get_all(conn).map_err(AppError::read("container"))?;
+    let users = User::get_all(conn).map_err(AppError::read("user"))?;
+    let pages = total.div_ceil(PAGE_SIZE.into()).max(1) as u32;
+    let shown = FilterForm {
+        filter: &filter,
+        from,
+        to,
+    };
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Datki" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                (contributions_filter(&shown, &containers, &users))
+                p.text-neutral-500 {
+                    "Znaleziono: " (total)
+                }
+                (contribution_table(&entries))
+                (contributions_pagination(&shown, page, pages))
+            }
+        }
+    }
+    .into_response())
+}
+
+/// The filters as they're put back into forms: the applied [`ContributionFilter`],
+/// plus the dates as they were typed, as the filter holds times.
+struct FilterForm<'a> {
+    filter: &'a ContributionFilter,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl FilterForm<'_> {
+    /// Every active filter as a query parameter, for links and forms that keep them.
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let f = self.filter;
+        let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string());
+        [
+            ("pojemnik", f.container.map(|c| c.to_string())),
+            ("konto", f.recorded_by.map(|a| a.to_string())),
+            ("od", date(self.from)),
+            ("do", date(self.to)),
+            ("min", f.min_amount.map(Money::to_input)),
+            ("max", f.max_amount.map(Money::to_input)),
+            ("szukaj", f.search.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+    }
+}
+
+fn contributions_filter(shown: &FilterForm, containers: &[Container], users: &[User]) -> Markup {
+    let f = shown.filter;
+    let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string());
+    let input = "px-2 border border-neutral-600 rounded bg-neutral-900";
+    html! {
+        form.flex.flex-col.gap-2 method="get" action="/panel/datki" {
+            .flex.flex-wrap.gap-2 {
+                select name="pojemnik" class=(input) {
+                    option value="" { "Wszystkie pojemniki" }
+                    @for c in containers {
+                        option value=(c.id) selected[f.container == Some(c.id)] { (c.name) }
+                    }
+                }
+                select name="konto" class=(input) {
+                    option value="" { "Wszystkie konta" }
+                    @for u in users {
+                        option value=(u.id) selected[f.recorded_by == Some(u.id)] { (u.handle) }
+                    }
+                }
+                input name="szukaj" placeholder="Szukaj w notatkach" value=[f.search.as_deref()]
+                    .flex-1 class=(input);
+            }
+            .flex.flex-wrap.gap-2.items-center {
+                label for="od" { "Od" }
+                input name="od" id="od" type="date" value=[date(shown.from)] class=(input);
+                label for="do" { "do" }
+                input name="do" id="do" type="date" value=[date(shown.to)] class=(input);
+                input name="min" placeholder="Kwota od" inputmode="decimal" size="8"
+                    value=[f.min_amount.map(Money::to_input)] class=(input);
+                input name="max" placeholder="Kwota do" inputmode="decimal" size="8"
+                    value=[f.max_amount.map(Money::to_input)] class=(input);
+                button type="submit" .ml-auto.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Filtruj" }
+            }
+        }
+    }
+}
+
+/// Previous/next buttons as GET forms, so that the active filters carry over.
+fn contributions_pagination(shown: &FilterForm, page: u32, pages: u32) -> Markup {
+    let params = shown.params();
+    let page_form = |target: u32, label: &str| {
+        html! {
+            form method="get" action="/panel/datki" {
+                @for (name, value) in &params {
+                    input type="hidden" name=(name) value=(value);
+                }
+                input type="hidden" name="strona" value=(target);
+                button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { (label) }
+            }
+        }
+    };
+    html! {
+        .flex.justify-between.items-center {
+            @if page > 1 { (page_form(page - 1, "Nowsze")) } @else { span {} }
+            p.text-neutral-500 { "Strona " (page) " z " (pages) }
+            @if page < pages { (page_form(page + 1, "Starsze")) } @else { span {} }
+        }
+    }
+}
+
+fn contribution_table(entries: &[ContributionEntry]) -> Markup {
+    html! {
+        @if entries.is_empty() {
+            p.text-center { "Brak datków." }
+        }
+        @for e in entries {
+            a href=(format!("/panel/datki/{}", e.id)) .flex.flex-col.border-t.border-neutral-600.pt-2.hover:bg-neutral-700 {
+                .flex.flex-wrap.justify-between.gap-2 {
+                    p {
+                        @if e.voided_at.is_some() {
+                            span.font-serif.text-lg.line-through.text-neutral-500 { (e.amount) }
+                        } @else {
+                            span.font-serif.text-lg { (e.amount) }
+                        }
+                        " → " (container_name(e))
+                        @if e.voided_at.is_some() {
+                            span.text-neutral-500 { " (unieważniony)" }
+                        } @else if e.original_amount.is_some() {
+                            span.text-neutral-500 { " (poprawiony)" }
+                        }
+                    }
+                    p.text-neutral-500 { (local_time(e.recorded_at)) }
+                }
+                p.text-neutral-500 { (recorder(e)) }
+                @if let Some(notes) = &e.notes {
+                    p.break-words { (notes) }
+                }
+            }
+        }
+    }
+}
+
+/// The coins and notes a contribution was counted from, as they were counted; later
+/// corrections change the amount, but not this.
+fn cash_breakdown(count: &CashCount) -> Markup {
+    html! {
+        p.mt-2 { "Przeliczenie gotówki:" }
+        table.w-full.text-sm.mb-2 {
+            @for (denomination, quantity) in count.iter() {
+                tr.border-t.border-neutral-700 {
+                    td { (denomination_label(denomination)) }
+                    td.text-right { "× " (quantity) }
+                    td.text-right {
+                        @if let Ok(subtotal) = Money::try_from(denomination * i64::from(quantity)) {
+                            (subtotal)
+                        }
+                    }
+                }
+            }
+            tr.border-t.border-neutral-600 {
+                td { "Razem" }
+                td.text-right { (count.pieces()) " szt." }
+                td.text-right {
+                    @if let Ok(total) = count.total() { (total) }
+                }
+            }
+        }
+    }
+}
+
+/// Correcting and voiding, both of which need a reason that goes into the history.
+fn contribution_amend(entry: &ContributionEntry, csrf: &CsrfToken) -> Markup {
+    let input = "px-2 border border-neutral-600 rounded bg-neutral-900";
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Korekta" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                form.flex.flex-wrap.gap-2 method="post" action=(format!("/panel/datki/{}/korekta", entry.id)) {
+                    (csrf)
+                    input name="contramt" inputmode="decimal" size="10" required
+                        value=(entry.amount.to_input()) class=(input);
+                    input name="reason" placeholder="Powód korekty" required .flex-1 class=(input);
+                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Popraw kwotę" }
+                }
+                form.flex.flex-wrap.gap-2 method="post" action=(format!("/panel/datki/{}/uniewaznij", entry.id)) {
+                    (csrf)
+                    input name="reason" placeholder="Powód unieważnienia" required .flex-1 class=(input);
+                    button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer { "Unieważnij" }
+                }
+                p.text-neutral-500.text-sm {
+                    "Unieważniony datek przestaje się liczyć do wyników, ale zostaje w historii."
+                }
+            }
+        }
+    }
+}
+
+fn container_name(e: &ContributionEntry) -> &str {
+    match (&e.container, &e.container_name) {
+        (_, Some(name)) => name,
+        (Some(_), None) => "(usunięty pojemnik)",
+        (None, None) => "(bez pojemnika)",
+    }
+}
+
+fn recorder(e: &ContributionEntry) -> &str {
+    match (&e.recorded_by, &e.recorder_handle) {
+        (_, Some(handle)) => handle,
+        (Some(_), None) => "(usunięte konto)",
+        (None, None) => "(nieznany)",
+    }
+}
+
+pub async fn controls_contribution(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Response {
+    state
+        .respond(move |conn| contribution_page(conn, &headers, &id))
+        .await
+}
+
+fn contribution_page(
+    conn: &Connection,
+    headers: &HeaderMap,
+    id: &Uuid,
+) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if let Err(e) = user.require(Permission::ViewContributions) {
+        return Ok(Flash::error(e.msg()).redirect("/panel").into_response());
+    }
+    let entry = match ContributionEntry::get(id, conn) {
+        Ok(e) => e,
+        Err(ContributionStructError::NotFound) => {
+            return Ok(Flash::error(ContributionStructError::NotFound.msg())
+                .redirect("/panel/datki")
+                .into_response());
+        }
+        Err(e) => return Err(AppError::read("contribution")(e)),
+    };
+    let amend = entry.voided_at.is_none() && entry.require_amend(&user).is_ok();
+    let history = match user.can(Permission::ViewLogs) {
+        false => None,
+        true => {
+            let filter = LogFilter {
+                entity_id: Some(id.to_string()),
+                ..LogFilter::default()
+            };
+            Some(
+                audit::query(conn, &filter, HISTORY_LIMIT, 0)
+                    .map_err(AppError::read("log"))?
+                    .0,
+            )
+        }
+    };
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Datek" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-1 {
+                @if let Some(voided_at) = entry.voided_at {
+                    p.font-serif.text-2xl.line-through.text-neutral-500 { (entry.amount) }
+                    p.text-red-400 {
+                        "Unieważniony " (local_time(voided_at))
+                        @if let Some(reason) = &entry.void_reason { ": " (reason) }
+                    }
+                } @else {
+                    p.font-serif.text-2xl { (entry.amount) }
+                }
+                @if let Some(original) = entry.original_amount {
+                    p.text-neutral-500 {
+                        "Pierwotnie odnotowano " (original)
+                        @if let Some(reason) = &entry.correction_reason { ", poprawiono: " (reason) }
+                    }
+                }
+                p { "Pojemnik: " (container_name(&entry)) }
+                p { "Odnotował(a): " (recorder(&entry)) }
+                p { "Czas: " (local_time(entry.recorded_at)) }
+                @if let Some(notes) = &entry.notes {
+                    p.break-words { "Notatka: " (notes) }
+                }
+                @if let Some(count) = &entry.cash_count {
+                    (cash_breakdown(count))
+                }
+                p.font-mono.text-sm.text-neutral-500 { (entry.id) }
+            }
+        }
+        @if amend {
+            (contribution_amend(&entry, csrf))
+        }
+        @if let Some(history) = history {
+            .mx-auto.max-w-3xl.p-4 {
+                p.font-serif.text-xl.ml-1 { "Historia zmian" }
+                .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                    (log_table(&history))
+                }
+            }
+        }
+        .mx-auto.max-w-3xl.px-4.flex {
+            a href="/panel/datki" .ml-auto.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700 { "Wszystkie datki" }
+        }
+    }
+    .into_response())
+}
+
+pub async fn controls_cash_count(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state
+        .respond(move |conn| cash_count_page(conn, &headers))
+        .await
+}
+
+fn cash_count_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if let Err(e) = user.require(Permission::RecordContributions) {
+        return Ok(Flash::error(e.msg()).redirect("/panel").into_response());
+    }
+    let containers = Container::get_active(conn).map_err(AppError::read("container"))?;
+    let input = "px-2 border border-neutral-600 rounded bg-neutral-900";
+    let (coins, notes) = DENOMINATIONS
+        .iter()
+        .partition::<Vec<i64>, _>(|&&d| d < SMALLEST_NOTE);
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Liczenie gotówki" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                @if containers.is_empty() {
+                    p.text-center { "Najpierw stwórz pojemnik!" }
+                } @else {
+                    form #cash-count .flex.flex-col.gap-3 method="post" action="/panel/liczenie" {
+                        (csrf)
+                        input type="hidden" name="contrkey" value=(Uuid::now_v7());
+                        label for="contrbank" { "Pojemnik" }
+                        select name="contrbank" id="contrbank" .p-2.border.border-neutral-600.rounded.bg-neutral-900 {
+                            @for container in &containers {
+                                option value=(container.id) { (container.name) }
+                            }
+                        }
+                        .grid.grid-cols-1.sm:grid-cols-2.gap-4 {
+                            (denomination_inputs("Monety", &coins, input))
+                            (denomination_inputs("Banknoty", &notes, input))
+                        }
+                        label for="contrdeclared" { "Zadeklarowana kwota " span.text-neutral-500 { "(opcjonalnie, np. z plomby)" } }
+                        input name="contrdeclared" id="contrdeclared" type="text" inputmode="decimal" placeholder="0,00" class=(input);
+                        label for="contrnote" { "Notatka do datku " span.text-neutral-500 { "(opcjonalnie)" } }
+                        input name="contrnote" id="contrnote" type="text" class=(input);
+                        .flex.justify-between.items-center {
+                            p { "Razem: " span #cash-total .font-serif.text-lg { (Money::ZERO) } }
+                            button type="submit" .p-1.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Odnotuj przeliczony datek" }
+                        }
+                    }
+                    script { (PreEscaped(JS_CASH_TOTAL)) }
+                }
+            }
+        }
+    }
+    .into_response())
+}
+
+fn denomination_inputs(title: &str, denominations: &[i64], input: &str) -> Markup {
+    html! {
+        .flex.flex-col.gap-1 {
+            p.text-neutral-500 { (title) }
+            @for &d in denominations {
+                .flex.items-center.gap-2 {
+                    label for=(format!("{}{d}", cash::FIELD_PREFIX)) .w-16.text-right { (denomination_label(d)) }
+                    span.text-neutral-500 { "×" }
+                    input type="number" min="0" step="1" inputmode="numeric" placeholder="0"
+                        name=(format!("{}{d}", cash::FIELD_PREFIX)) id=(format!("{}{d}", cash::FIELD_PREFIX))
+                        data-grosze=(d) .flex-1 class=(input);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use maud::{Markup, html};
+use rusqlite::Connection;
+
+use crate::{
+    config::Config,
+    error::AppError,
+    flash::Flash,
+    html::{
+        controls::{controls_notices, controls_user_witaj, panel_user},
+        head, local_time,
+    },
+    scheduler::JobStatus,
+    state::AppState,
+    users::{csrf::CsrfToken, roles::Permission},
+};
+
+pub async fn controls_jobs(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state.respond(move |conn| jobs_page(conn, &headers)).await
+}
+
+fn jobs_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if let Err(e) = user.require(Permission::ManageJobs) {
+        return Ok(Flash::error(e.msg()).redirect("/panel").into_response());
+    }
+    let jobs = JobStatus::load_all(conn).map_err(AppError::read("job"))?;
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Zadania w tle" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @for status in &jobs {
+                    (job_row(status, csrf))
+                }
+            }
+        }
+    }
+    .into_response())
+}
+
+fn job_row(status: &JobStatus, csrf: &CsrfToken) -> Markup {
+    html! {
+        .flex.flex-wrap.justify-between.items-center.gap-2.border-t.border-neutral-600.pt-2 {
+            .flex.flex-col {
+                p {
+                    (status.job.display_name)
+                    span.text-neutral-500 { " (" (status.job.schedule.describe()) ")" }
+                }
+                p.text-neutral-500.text-sm {
+                    @if status.is_running() {
+                        span.text-amber-400 { "w toku" }
+                    } @else {
+                        @match status.last_ok {
+                            Some(true) => span.text-green-400 { "powodzenie" },
+                            Some(false) => span.text-red-400 { "błąd" },
+                            None => "jeszcze nie uruchomione",
+                        }
+                    }
+                    @if let Some(at) = status.last_finished {
+                        " · ostatnio " (local_time(at))
+                    }
+                    " · następne " (local_time(status.next_run().max(Utc::now())))
+                }
+                @if let Some(message) = &status.last_message {
+                    p.text-sm.break-all { (message) }
+                }
+            }
+            form method="post" action=(format!("/panel/zadania/{}/uruchom", status.job.name)) {
+                (csrf)
+                button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Uruchom teraz" }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use maud::{Markup, html};
+use rusqlite::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, LogEntry, LogFilter},
+    config::Config,
+    error::AppError,
+    flash::Flash,
+    html::{
+        controls::{controls_notices, controls_user_witaj, panel_user},
+        head, local_time,
+    },
+    state::AppState,
+    users::{User, csrf::CsrfToken, roles::Permission},
+};
+
+const PAGE_SIZE: u32 = 50;
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    akcja: Option<String>,
+    konto: Option<String>,
+    obiekt: Option<String>,
+    strona: Option<String>,
+}
+
+pub async fn controls_logs_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<LogsQuery>,
+) -> Response {
+    state
+        .respond(move |conn| logs_page(conn, &headers, query))
+        .await
+}
+
+fn logs_page(
+    conn: &Connection,
+    headers: &HeaderMap,
+    query: LogsQuery,
+) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if let Err(e) = user.require(Permission::ViewLogs) {
+        return Ok(Flash::error(e.msg()).redirect("/panel").into_response());
+    }
+
+    let non_empty = |v: Option<String>| v.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+    let filter = LogFilter {
+        action: non_empty(query.akcja).and_then(|a| Action::from_str(&a).ok()),
+        actor: non_empty(query.konto).and_then(|a| Uuid::from_str(&a).ok()),
+        entity_id: non_empty(query.obiekt),
+    };
+    let page = non_empty(query.strona)
+        .and_then(|p| p.parse::<u32>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let (entries, total) = audit::query(
+        conn,
+        &filter,
+        PAGE_SIZE,
+        (page - 1).saturating_mul(PAGE_SIZE),
+    )
+    .map_err(AppError::read("log"))?;
+    let users = User::get_all(conn).map_err(AppError::read("user"))?;
+    let pages = total.div_ceil(PAGE_SIZE.into()).max(1) as u32;
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Rejestr aktywności" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                (logs_filter(&filter, &users))
+                (log_table(&entries))
+                (logs_pagination(&filter, page, pages))
+            }
+        }
+    }
+    .into_response())
+}
+
+fn logs_filter(filter: &LogFilter, users: &[User]) -> Markup {
+    html! {
+        form.flex.flex-wrap.gap-2 method="get" action="/panel/rejestr" {
+            select name="akcja" .px-2.border.border-neutral-600.rounded.bg-neutral-900 {
+                option value="" { "Wszystkie akcje" }
+                @for action in Action::ALL {
+                    option value=(action.as_str()) selected[filter.action == Some(action)] {
+                        (action.display_name())
+                    }
+                }
+            }
+            select name="konto" .px-2.border.border-neutral-600.rounded.bg-neutral-900 {
+                option value="" { "Wszystkie konta" }
+                @for u in users {
+                    option value=(u.id) selected[filter.actor == Some(u.id)] { (u.handle) }
+                }
+            }
+            input name="obiekt" placeholder="Identyfikator obiektu" value=[filter.entity_id.as_deref()]
+                .flex-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+            button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Filtruj" }
+        }
+    }
+}
+
+/// Previous/next buttons as GET forms, so that the active filters carry over.
+fn logs_pagination(filter: &LogFilter, page: u32, pages: u32) -> Markup {
+    let page_form = |target: u32, label: &str| {
+        html! {
+            form method="get" action="/panel/rejestr" {
+                @if let Some(action) = filter.action {
+                    input type="hidden" name="akcja" value=(action.as_str());
+                }
+                @if let Some(actor) = filter.actor {
+                    input type="hidden" name="konto" value=(actor);
+                }
+                @if let Some(entity_id) = &filter.entity_id {
+                    input type="hidden" name="obiekt" value=(entity_id);
+                }
+                input type="hidden" name="strona" value=(target);
+                button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { (label) }
+            }
+        }
+    };
+    html! {
+        .flex.justify-between.items-center {
+            @if page > 1 { (page_form(page - 1, "Nowsze")) } @else { span {} }
+            p.text-neutral-500 { "Strona " (page) " z " (pages) }
+            @if page < pages { (page_form(page + 1, "Starsze")) } @else { span {} }
+        }
+    }
+}
+
+/// Log entries as a list, each with its actor, target and before/after snapshots.
+pub fn log_table(entries: &[LogEntry]) -> Markup {
+    html! {
+        @if entries.is_empty() {
+            p.text-center { "Brak wpisów." }
+        }
+        @for e in entries {
+            .flex.flex-col.border-t.border-neutral-600.pt-2 {
+                .flex.flex-wrap.justify-between.gap-2 {
+                    p { (e.action.display_name()) }
+                    p.text-neutral-500 { (local_time(e.at)) }
+                }
+                p.text-neutral-500 {
+                    @match (&e.actor, &e.actor_handle) {
+                        (Some(id), Some(handle)) => a href=(format!("/panel/rejestr?konto={id}")) { (handle) },
+                        (Some(_), None) => "(usunięte konto)",
+                        (None, _) => "(system)",
+                    }
+                    @if let Some(entity_id) = &e.entity_id {
+                        " → " (e.action.entity_type()) " "
+                        a.font-mono href=(format!("/panel/rejestr?obiekt={entity_id}")) { (entity_id) }
+                    }
+                }
+                @if e.before.is_some() || e.after.is_some() {
+                    p.font-mono.text-sm.break-all {
+                        (e.before.as_deref().unwrap_or("∅")) " → " (e.after.as_deref().unwrap_or("∅"))
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,114 +1,153 @@
 use axum::{
-    extract::Query,
-    http::{HeaderMap, StatusCode, header},
+    extract::State,
+    http::{HeaderMap, header},
     response::{IntoResponse, Response},
 };
+use chrono::NaiveDate;
 use maud::{Markup, PreEscaped, html};
-use serde::Deserialize;
+use rusqlite::Connection;
+use uuid::Uuid;
 
 pub mod containers;
+pub mod contributions;
+pub mod jobs;
+pub mod logs;
+pub mod sessions;
+pub mod settings;
 
 use crate::{
-    database::open_db,
-    html::{JS_CLEAN_QUERY, SVG_PACKAGE_OPEN, SVG_SETTINGS, head},
-    users::{User, auth::COOKIE_CLEAR},
+    audit::{self, LogEntry, LogFilter},
+    config::Config,
+    containers::Container,
+    contributions::ContributionEntry,
+    error::AppError,
+    flash::{Flash, FlashRedirect},
+    html::{SVG_PACKAGE_OPEN, SVG_SETTINGS, controls::logs::log_table, head},
+    money::Money,
+    state::AppState,
+    users::{User, auth::COOKIE_CLEAR, csrf::CsrfToken, roles::Permission},
 };
 
-#[derive(Deserialize)]
-pub struct LoginErrorQuery {
-    pub error: Option<String>,
+pub async fn controls(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state
+        .respond(move |conn| controls_page(conn, &headers))
+        .await
 }
-pub async fn controls(headers: HeaderMap, Query(query): Query<LoginErrorQuery>) -> Response {
-    let conn = match open_db() {
-        Ok(c) => c,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Couldn't open database").into_response();
-        }
-    };
-    let (user, error_msg) = match User::authenticate(&headers, &conn) {
-        Ok(user) => (user, query.error),
-        Err(e) => (None, Some(e.msg().to_string())),
+
+fn controls_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let (user, flash) = match User::authenticate(headers, conn) {
+        // API tokens get the login form, like anyone else without a session
+        Ok(user) => (
+            user.filter(|u| u.require_session().is_ok()),
+            Flash::from_headers(headers),
+        ),
+        Err(e) => (None, Some(Flash::error(e.msg()))),
     };
-    let defcontramt = match conn
-        .prepare("SELECT default_contribution_amount FROM config WHERE id_zero = 0")
-        .unwrap()
-        .query_one([], |r| Ok(r.get::<_, u32>(0).unwrap()))
-    {
-        Ok(d) => d,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read config data",
-            )
-                .into_response();
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    let containers = Container::get_active(conn).map_err(AppError::read("container"))?;
+    let last_contribution = match user.as_ref() {
+        Some(u) => {
+            ContributionEntry::last_undoable(&u.id, conn).map_err(AppError::read("contribution"))?
         }
+        None => None,
     };
-    let containers = match conn
-        .prepare("SELECT id, name FROM containers")
-        .unwrap()
-        .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))
-        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
-    {
-        Ok(c) => c,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read container data",
-            )
-                .into_response();
-        }
+
+    let recent_logs = match user.as_ref().filter(|u| u.can(Permission::ViewLogs)) {
+        None => None,
+        Some(_) => Some(
+            audit::query(conn, &LogFilter::default(), RECENT_LOGS, 0)
+                .map_err(AppError::read("log"))?
+                .0,
+        ),
     };
+    let clear_cookie = user.is_none() && matches!(flash, Some(Flash::Error(_)));
 
-    (
-        [if error_msg.is_some() {
+    Ok((
+        [if clear_cookie {
             (header::SET_COOKIE.as_str(), COOKIE_CLEAR)
         } else {
             ("auth", "good")
         }],
         html! {
-            (head("Zbiorywalizacja WPiK"))
+            (head(&config.competition_name))
             body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
             .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
-                a href="/" { p { "Zbiorywalizacja WPiK" } }
+                a href="/" { p { (config.competition_name) } }
                 p { "Panel kontrolny" }
             }
             @if let Some(u) = user {
-                (controls_user_witaj(u))
+                (controls_user_witaj(&u, csrf))
+                (controls_totp_required(&u))
                 (controls_user_witaj_links())
-                (controls_new_contributions(&containers, defcontramt))
-                // (controls_logs())
-                // (controls_globalconf())
+                (controls_notices(flash))
+                @if u.can(Permission::RecordContributions) {
+                    (controls_new_contributions(
+                        &containers,
+                        config.default_contribution_amount,
+                        last_contribution.as_ref(),
+                        csrf,
+                    ))
+                }
+                @if let Some(entries) = recent_logs {
+                    (controls_logs(&entries))
+                }
+                @if u.can(Permission::ManageConfig) {
+                    (controls_globalconf(&config, csrf))
+                }
             }
             @else {
-                (controls_user_login(error_msg))
+                (controls_user_login(flash))
             }
         },
     )
-        .into_response()
+        .into_response())
 }
 
-fn controls_new_contributions(containers: &[(String, String)], default_contramt: u32) -> Markup {
+fn controls_new_contributions(
+    containers: &[Container],
+    default_contramt: Money,
+    last: Option<&ContributionEntry>,
+    csrf: &CsrfToken,
+) -> Markup {
     html! {
         .mx-auto.max-w-3xl.p-4 {
             p.font-serif.text-xl.ml-1 { "Nowy datek" }
             .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
-                @if containers.len() == 0 {
+                @if let Some(last) = last {
+                    form.flex.flex-wrap.justify-between.items-center.gap-2.mb-3.pb-3.border-b.border-neutral-600
+                        method="post" action=(format!("/panel/datki/{}/cofnij", last.id)) {
+                        (csrf)
+                        p {
+                            "Ostatnio odnotowano " (last.amount)
+                            @if let Some(name) = &last.container_name { " → " (name) }
+                            "."
+                        }
+                        button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Cofnij" }
+                    }
+                }
+                @if containers.is_empty() {
                     p.text-center { "Najpierw stwórz pojemnik!" }
                 } @else {
-                    form .flex.flex-col.gap-1 {
+                    form .flex.flex-col.gap-1 method="post" action="/panel/datki" {
+                        (csrf)
+                        input type="hidden" name="contrkey" value=(Uuid::now_v7());
                         label for="contrbank" .mr-4 { "Pojemnik" }
                         select name="contrbank" id="contrbank" .mb-3.p-2.border.border-neutral-600.rounded.bg-neutral-900 {
-                            @for (id, name) in containers {
-                                option value=(id) { (name) }
+                            @for container in containers {
+                                option value=(container.id) { (container.name) }
                             }
                         }
                         label for="contramt" .mr-4{"Wielkość datku " span.text-neutral-500{"(w zł)"} }
-                        input name="contramt" type="number" step="0.01" min="0" required
-                            value=(format!("{:.2}", default_contramt as f64 / 100.0))
+                        input name="contramt" id="contramt" inputmode="decimal" required
+                            value=(default_contramt.to_input())
                             .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
-                        label for "contrnote" .mr-4{"Notatka do datku " span.text-neutral-500{"(opcjonalnie)"}}
-                        input name="contrnote" type="text" .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
-                        button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Odnotuj datek" }
+                        label for="contrnote" .mr-4{"Notatka do datku " span.text-neutral-500{"(opcjonalnie)"}}
+                        input name="contrnote" id="contrnote" type="text" .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                        .flex.justify-between.items-center {
+                            a href="/panel/liczenie" .text-neutral-500.hover:text-neutral-300 { "Przelicz gotówkę z pojemnika" }
+                            button type="submit" .p-1.px-2.border.border-neutral-600.rounded { "Odnotuj datek" }
+                        }
                     }
                 }
             }
@@ -116,23 +155,105 @@ fn controls_new_contributions(containers: &[(String, String)], default_contramt:
     }
 }
 
-fn controls_globalconf() -> Markup {
+fn controls_notices(flash: Option<Flash>) -> Markup {
+    html! {
+        @if let Some(flash) = flash {
+            .mx-auto.max-w-3xl.px-4 {
+                @match flash {
+                    Flash::Error(error_msg) => {
+                        .p-3.bg-red-900.bg-opacity-50.border.border-red-700.rounded.text-red-200 {
+                            p { (error_msg) }
+                        }
+                    }
+                    Flash::Success(success_msg) => {
+                        .p-3.bg-green-900.bg-opacity-50.border.border-green-700.rounded.text-green-200 {
+                            p { (success_msg) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn controls_globalconf(config: &Config, csrf: &CsrfToken) -> Markup {
+    let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string());
     html! {
         .mx-auto.max-w-3xl.p-4 {
             p.font-serif.text-xl.ml-1 { "Ustawienia zbiorywalizacji" }
             .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
-
+                form .flex.flex-col.gap-1 method="post" action="/panel/konfiguracja" {
+                    (csrf)
+                    label for="name" .mr-4 { "Nazwa" }
+                    input name="name" id="name" required maxlength="80" value=(config.competition_name)
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    label for="defcontramt" .mr-4 { "Domyślna wielkość datku " span.text-neutral-500 { "(w zł)" } }
+                    input name="defcontramt" id="defcontramt" inputmode="decimal" required
+                        value=(config.default_contribution_amount.to_input())
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    .flex.flex-col.sm:flex-row.gap-2.mb-3 {
+                        .flex.flex-col.gap-1.flex-1 {
+                            label for="start" { "Początek zbiórki " span.text-neutral-500 { "(opcjonalnie)" } }
+                            input name="start" id="start" type="date" value=[date(config.campaign_start)]
+                                .py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                        }
+                        .flex.flex-col.gap-1.flex-1 {
+                            label for="end" { "Koniec zbiórki " span.text-neutral-500 { "(opcjonalnie)" } }
+                            input name="end" id="end" type="date" value=[date(config.campaign_end)]
+                                .py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                        }
+                    }
+                    label.flex.gap-2 {
+                        input type="checkbox" name="stats_public" checked[config.stats_public];
+                        "Wyniki widoczne bez logowania"
+                    }
+                    label.flex.gap-2 {
+                        input type="checkbox" name="show_sum" checked[config.show_sum];
+                        "Pokazuj łączną sumę datków"
+                    }
+                    label.flex.gap-2.mb-3 {
+                        input type="checkbox" name="show_timeline" checked[config.show_timeline];
+                        "Pokazuj wykres przebiegu zbiórki"
+                    }
+                    label for="logo_url" .mr-4 { "Adres logo " span.text-neutral-500 { "(opcjonalnie)" } }
+                    input name="logo_url" id="logo_url" value=[config.logo_url.as_deref()]
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Zapisz ustawienia" }
+                }
             }
         }
     }
 }
 
-fn controls_logs() -> Markup {
+/// The user a panel subpage is shown to, or where to send the visitor instead: back to
+/// the panel unless they're logged in, and to the settings while their role requires
+/// two-factor authentication they haven't set up yet. The panel isn't for API tokens.
+fn panel_user(
+    headers: &HeaderMap,
+    conn: &Connection,
+) -> Result<Result<User, FlashRedirect>, AppError> {
+    let Some(user) = User::authenticate(headers, conn)? else {
+        return Ok(Err(FlashRedirect::to("/panel")));
+    };
+    user.require_session()?;
+    if user.needs_totp {
+        return Ok(Err(
+            Flash::error(TOTP_REQUIRED_MSG).redirect("/panel/ustawienia")
+        ));
+    }
+    Ok(Ok(user))
+}
+const TOTP_REQUIRED_MSG: &str =
+    "Twoja rola wymaga weryfikacji dwuetapowej. Włącz ją, aby korzystać z panelu.";
+
+const RECENT_LOGS: u32 = 5;
+fn controls_logs(entries: &[LogEntry]) -> Markup {
     html! {
         .mx-auto.max-w-3xl.p-4 {
             p.font-serif.text-xl.ml-1 { "Rejestr aktywności" }
-            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
-
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                (log_table(entries))
+                a href="/panel/rejestr" .ml-auto.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700 { "Pełny rejestr" }
             }
         }
     }
@@ -140,15 +261,20 @@ fn controls_logs() -> Markup {
 
 const WITAJ_LINKS: &[(&str, &str)] = &[
     ("Pojemniki", "/panel/pojemniki"),
+    ("Datki", "/panel/datki"),
     ("Ustawienia & konta", "/panel/ustawienia"),
 ];
-fn controls_user_witaj(u: User) -> Markup {
+fn controls_user_witaj(u: &User, csrf: &CsrfToken) -> Markup {
     html! {
         .mx-auto.max-w-3xl.p-4.pb-0 {
             .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
                 .flex.flex-row.justify-between {
-                    p.font-serif.text-center.text-xl { "Witaj, " (u.handle) "!" }
+                    p.font-serif.text-center.text-xl {
+                        "Witaj, " (u.handle) "! "
+                        span.text-neutral-500.text-base { "(" (u.role.display_name()) ")" }
+                    }
                     form.flex.justify-center method="post" action="/logout" {
+                        (csrf)
                         button.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer
                             type="submit" { "Wyloguj się" }
                     }
@@ -180,16 +306,69 @@ fn controls_user_witaj_links() -> Markup {
     }
 }
 
-fn controls_user_login(error_msg: Option<String>) -> Markup {
+/// Tells a user whose role requires two-factor authentication that they have to set it up
+/// before they can do anything else.
+fn controls_totp_required(u: &User) -> Markup {
     html! {
+        @if u.needs_totp {
+            .mx-auto.max-w-3xl.px-4.pt-4 {
+                .p-3.bg-red-900.bg-opacity-50.border.border-red-700.rounded.text-red-200 {
+                    p {
+                        "Twoja rola wymaga weryfikacji dwuetapowej. "
+                        a.underline href="/panel/ustawienia" { "Włącz ją w ustawieniach" }
+                        ", aby korzystać z panelu."
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Second step of logging in, for accounts with two-factor authentication.
+pub fn controls_totp_login(
+    competition_name: &str,
+    challenge: &str,
+    error_msg: Option<&str>,
+) -> Markup {
+    html! {
+        (head(competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (competition_name) } }
+            p { "Panel kontrolny" }
+        }
         .mx-auto.max-w-3xl.p-4 {
             .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
-                p.font-serif.mb-4.text-center.text-xl { "Panel niedostępny bez uwierzytelnienia." }
+                p.font-serif.mb-4.text-center.text-xl { "Weryfikacja dwuetapowa" }
                 @if let Some(error_msg) = error_msg {
                     .mb-4.p-3.bg-red-900.bg-opacity-50.border.border-red-700.rounded.text-red-200 {
                         p { (error_msg) }
                     }
-                    script { (JS_CLEAN_QUERY) }
+                }
+                p.mb-4.text-center.text-neutral-500 {
+                    "Wpisz kod z aplikacji uwierzytelniającej albo jeden z kodów odzyskiwania."
+                }
+                form.flex.gap-2.flex-wrap.justify-center method="post" action="/login/2fa" {
+                    input type="hidden" name="challenge" value=(challenge);
+                    input.px-2.border.border-neutral-600.rounded.bg-neutral-900
+                        name="code" placeholder="Kod" required autofocus autocomplete="one-time-code" {}
+                    button.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer
+                        type="submit" { "Zaloguj się" }
+                }
+            }
+        }
+    }
+}
+
+fn controls_user_login(flash: Option<Flash>) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                p.font-serif.mb-4.text-center.text-xl { "Panel niedostępny bez uwierzytelnienia." }
+                @if let Some(Flash::Error(error_msg)) = flash {
+                    .mb-4.p-3.bg-red-900.bg-opacity-50.border.border-red-700.rounded.text-red-200 {
+                        p { (error_msg) }
+                    }
                 }
                 form.flex.gap-2.flex-wrap method="post" action="/login" {
                     input.px-2.border.border-neutral-600.rounded.bg-neutral-900
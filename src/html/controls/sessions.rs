@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use maud::{Markup, html};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::AppError,
+    flash::Flash,
+    html::{
+        controls::{controls_notices, controls_user_witaj, panel_user},
+        head, local_time,
+    },
+    state::AppState,
+    users::{User, auth::cookie_token, csrf::CsrfToken, roles::Permission, sessions::Session},
+};
+
+pub async fn controls_sessions(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state
+        .respond(move |conn| sessions_page(conn, &headers))
+        .await
+}
+
+fn sessions_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    let user = match panel_user(headers, conn)? {
+        Ok(u) => u,
+        Err(to) => return Ok(to.into_response()),
+    };
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    let current = cookie_token(headers)
+        .and_then(|t| Session::get_by_token(t, conn).ok())
+        .map(|s| *s.id());
+    let own = Session::get_active(Some(&user.id), conn).map_err(AppError::read("session"))?;
+    let others = match user.can(Permission::ManageUsers) {
+        false => None,
+        true => {
+            let sessions = Session::get_active(None, conn).map_err(AppError::read("session"))?;
+            let handles = User::get_all(conn)
+                .map_err(AppError::read("user"))?
+                .into_iter()
+                .map(|u| (u.id, u.handle))
+                .collect::<HashMap<Uuid, String>>();
+            Some(
+                sessions
+                    .into_iter()
+                    .filter(|s| s.user_id() != &user.id)
+                    .map(|s| {
+                        let handle = handles.get(s.user_id()).cloned().unwrap_or_default();
+                        (handle, s)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_notices(Flash::from_headers(headers)))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Twoje sesje" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @for s in &own {
+                    (session_row(s, None, current.as_ref() == Some(s.id()), csrf))
+                }
+                @if own.len() > 1 && current.is_some() {
+                    form.ml-auto method="post" action="/panel/sesje/pozostale" {
+                        (csrf)
+                        button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer {
+                            "Wyloguj wszystkie pozostałe sesje"
+                        }
+                    }
+                }
+            }
+        }
+        @if let Some(others) = others {
+            .mx-auto.max-w-3xl.p-4 {
+                p.font-serif.text-xl.ml-1 { "Sesje pozostałych kont" }
+                .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                    @if others.is_empty() {
+                        p.text-center { "Brak aktywnych sesji." }
+                    }
+                    @for (handle, s) in &others {
+                        (session_row(s, Some(handle), false, csrf))
+                    }
+                }
+            }
+        }
+    }
+    .into_response())
+}
+
+fn session_row(s: &Session, handle: Option<&str>, current: bool, csrf: &CsrfToken) -> Markup {
+    html! {
+        .flex.flex-wrap.justify-between.items-center.gap-2.border-t.border-neutral-600.pt-2 {
+            .flex.flex-col {
+                p {
+                    @if let Some(handle) = handle {
+                        (handle) " · "
+                    }
+                    (s.user_agent().unwrap_or("nieznana przeglądarka"))
+                    @if current {
+                        span.text-green-400 { " (ta sesja)" }
+                    }
+                }
+                p.text-neutral-500.text-sm {
+                    "IP " (s.ip().unwrap_or("?"))
+                    " · zalogowano " (local_time(s.issued()))
+                    " · ostatnio aktywna " (local_time(s.last_access()))
+                }
+            }
+            @if !current {
+                form method="post" action=(format!("/panel/sesje/{}/uniewaznij", s.id())) {
+                    (csrf)
+                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Unieważnij" }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response},
+};
+use maud::{Markup, html};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::AppError,
+    flash::Flash,
+    html::{
+        controls::{controls_notices, controls_totp_required, controls_user_witaj},
+        head, local_time,
+    },
+    state::AppState,
+    users::{
+        MIN_PASSWORD_LEN, User,
+        csrf::CsrfToken,
+        roles::{Permission, Role},
+        tokens::{ApiToken, Scope},
+        totp::{self, TotpStatus, otpauth_uri},
+    },
+};
+
+pub async fn controls_settings(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state
+        .respond(move |conn| settings_page(conn, &headers))
+        .await
+}
+
+fn settings_page(conn: &Connection, headers: &HeaderMap) -> Result<Response, AppError> {
+    let csrf = &CsrfToken::from_headers(headers);
+    // the one page open to users who still have to set up two-factor authentication
+    let user = match User::authenticate(headers, conn)? {
+        Some(u) => u,
+        None => return Ok(Redirect::to("/panel").into_response()),
+    };
+    user.require_session()?;
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    let users = match user.can(Permission::ManageUsers) {
+        false => None,
+        true => Some((
+            User::get_all(conn).map_err(AppError::read("user"))?,
+            totp::enabled_users(conn).map_err(AppError::read("user"))?,
+            totp::required_roles(conn).map_err(AppError::read("config"))?,
+        )),
+    };
+    let tokens = ApiToken::get_for_user(&user.id, conn).map_err(AppError::read("API token"))?;
+    let totp_status =
+        totp::status(&user.id, conn).map_err(AppError::read("two-factor authentication"))?;
+
+    Ok(html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (config.competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(&user, csrf))
+        (controls_totp_required(&user))
+        (controls_notices(Flash::from_headers(headers)))
+        (change_password(csrf))
+        (two_factor(&user, &totp_status, &config.competition_name, csrf))
+        .mx-auto.max-w-3xl.px-4 {
+            a href="/panel/sesje" .block.p-2.border.border-neutral-600.bg-neutral-800.rounded.hover:bg-neutral-700 {
+                "Aktywne sesje i urządzenia"
+            }
+            @if user.can(Permission::ManageJobs) {
+                a href="/panel/zadania" .block.mt-2.p-2.border.border-neutral-600.bg-neutral-800.rounded.hover:bg-neutral-700 {
+                    "Zadania w tle"
+                }
+            }
+        }
+        (api_tokens(&tokens, csrf))
+        @if let Some((users, enabled, required)) = users {
+            (users_list(users, &enabled, csrf))
+            (totp_roles(&required, csrf))
+            (new_user(csrf))
+        }
+    }
+    .into_response())
+}
+
+fn change_password(csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Zmiana hasła" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                form .flex.flex-col.gap-1 method="post" action="/panel/ustawienia/haslo" {
+                    (csrf)
+                    label for="current" .mr-4 { "Obecne hasło" }
+                    input name="current" id="current" type="password" required
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    label for="new" .mr-4 { "Nowe hasło " span.text-neutral-500 { "(min. " (MIN_PASSWORD_LEN) " znaków)" } }
+                    input name="new" id="new" type="password" required minlength=(MIN_PASSWORD_LEN)
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    label for="repeat" .mr-4 { "Powtórz nowe hasło" }
+                    input name="repeat" id="repeat" type="password" required minlength=(MIN_PASSWORD_LEN)
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Zmień hasło" }
+                }
+            }
+        }
+    }
+}
+
+fn code_input() -> Markup {
+    html! {
+        input name="code" placeholder="Kod" required autocomplete="one-time-code"
+            .flex-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+    }
+}
+
+fn two_factor(user: &User, status: &TotpStatus, issuer: &str, csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Weryfikacja dwuetapowa" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @if status.enabled {
+                    p {
+                        "Weryfikacja dwuetapowa jest włączona. Pozostałe kody odzyskiwania: "
+                        (status.recovery_codes_left) "."
+                    }
+                    form.flex.gap-2 method="post" action="/panel/ustawienia/2fa/kody" {
+                        (csrf)
+                        (code_input())
+                        button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Nowe kody odzyskiwania" }
+                    }
+                    form.flex.gap-2 method="post" action="/panel/ustawienia/2fa/wylacz" {
+                        (csrf)
+                        (code_input())
+                        button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer { "Wyłącz" }
+                    }
+                } @else if let Some(secret) = &status.pending {
+                    p {
+                        "Dodaj ten klucz do aplikacji uwierzytelniającej (np. Aegis albo Google Authenticator), "
+                        "a potem wpisz wygenerowany przez nią kod."
+                    }
+                    p.font-mono.break-all.p-2.border.border-neutral-600.rounded.bg-neutral-900 { (secret) }
+                    @let uri = otpauth_uri(secret, &user.handle, issuer);
+                    a.font-mono.text-sm.break-all.text-neutral-500 href=(uri) { (uri) }
+                    form.flex.gap-2 method="post" action="/panel/ustawienia/2fa/potwierdz" {
+                        (csrf)
+                        (code_input())
+                        button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Włącz" }
+                    }
+                } @else {
+                    p { "Logowanie będzie wymagać oprócz hasła kodu z aplikacji uwierzytelniającej." }
+                    form method="post" action="/panel/ustawienia/2fa" {
+                        (csrf)
+                        button type="submit" .p-1.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Skonfiguruj" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shown once right after recovery codes are generated, as they're only stored hashed.
+pub fn recovery_codes_page(
+    competition_name: &str,
+    user: &User,
+    codes: &[String],
+    csrf: &CsrfToken,
+) -> Markup {
+    html! {
+        (head(competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(user, csrf))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Kody odzyskiwania" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                .grid.grid-cols-2.gap-2.font-mono.p-2.border.border-neutral-600.rounded.bg-neutral-900 {
+                    @for code in codes { p { (code) } }
+                }
+                p.text-neutral-500 {
+                    "Zapisz te kody w bezpiecznym miejscu – nie będzie można ich wyświetlić ponownie. "
+                    "Każdy z nich zastąpi jednorazowo kod z aplikacji, gdy nie będzie jej pod ręką."
+                }
+                a href="/panel/ustawienia" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Wróć do ustawień" }
+            }
+        }
+    }
+}
+
+fn api_tokens(tokens: &[ApiToken], csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Tokeny API" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @for t in tokens {
+                    .flex.flex-wrap.justify-between.items-center.gap-2 {
+                        .flex.flex-col {
+                            p { (t.name) span.text-neutral-500 { " (" (scope_names(&t.scopes)) ")" } }
+                            p.text-neutral-500 {
+                                "Utworzony " (local_time(t.created_at))
+                                ", "
+                                @match t.last_used {
+                                    Some(at) => { "ostatnio użyty " (local_time(at)) },
+                                    None => "jeszcze nieużyty",
+                                }
+                            }
+                        }
+                        form method="post" action=(format!("/panel/ustawienia/tokeny/{}/usun", t.id)) {
+                            (csrf)
+                            button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer { "Unieważnij" }
+                        }
+                    }
+                }
+                form .flex.flex-col.gap-1.border-t.border-neutral-600.pt-3 method="post" action="/panel/ustawienia/tokeny" {
+                    (csrf)
+                    label for="token-name" .mr-4 { "Nazwa nowego tokenu" }
+                    input name="name" id="token-name" required
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    @for scope in Scope::ALL {
+                        label.flex.gap-2 {
+                            input type="checkbox" name=(scope.as_str());
+                            (scope.display_name())
+                            span.font-mono.text-neutral-500 { (scope.as_str()) }
+                        }
+                    }
+                    button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Utwórz token" }
+                }
+            }
+        }
+    }
+}
+
+/// Shown once right after a token is created, as its secret can't be looked up later.
+pub fn new_token_page(
+    competition_name: &str,
+    user: &User,
+    token: &ApiToken,
+    secret: &str,
+    csrf: &CsrfToken,
+) -> Markup {
+    html! {
+        (head(competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        (controls_user_witaj(user, csrf))
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Nowy token API" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                p { (token.name) span.text-neutral-500 { " (" (scope_names(&token.scopes)) ")" } }
+                p.font-mono.break-all.p-2.border.border-neutral-600.rounded.bg-neutral-900 { (secret) }
+                p.text-neutral-500 {
+                    "Skopiuj token teraz – nie będzie można go wyświetlić ponownie. "
+                    "Używaj go w nagłówku " span.font-mono { "Authorization: Bearer …" } "."
+                }
+                a href="/panel/ustawienia" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Wróć do ustawień" }
+            }
+        }
+    }
+}
+
+fn scope_names(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|s| s.display_name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn users_list(users: Vec<User>, totp_enabled: &HashSet<Uuid>, csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Konta" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                @for u in users {
+                    .flex.flex-col.gap-1 {
+                        p {
+                            (u.handle)
+                            span.text-neutral-500 { " (" (u.role.display_name()) ")" }
+                            @if totp_enabled.contains(&u.id) {
+                                span.text-neutral-500 { " · 2FA" }
+                            }
+                        }
+                        .flex.flex-wrap.gap-2 {
+                            @if !u.id.is_max() {
+                                form.flex.gap-2 method="post" action=(format!("/panel/ustawienia/konta/{}/rola", u.id)) {
+                                    (csrf)
+                                    (role_select(&format!("role-{}", u.id), u.role))
+                                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Zmień rolę" }
+                                }
+                            }
+                            form.flex.gap-2.flex-1 method="post" action=(format!("/panel/ustawienia/konta/{}/haslo", u.id)) {
+                                (csrf)
+                                input name="password" type="password" placeholder="Nowe hasło" required minlength=(MIN_PASSWORD_LEN)
+                                    .flex-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                                button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Zresetuj hasło" }
+                            }
+                            @if totp_enabled.contains(&u.id) {
+                                form method="post" action=(format!("/panel/ustawienia/konta/{}/2fa/wylacz", u.id)) {
+                                    (csrf)
+                                    button type="submit" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700.cursor-pointer { "Wyłącz 2FA" }
+                                }
+                            }
+                            @if !u.id.is_max() {
+                                form method="post" action=(format!("/panel/ustawienia/konta/{}/usun", u.id)) {
+                                    (csrf)
+                                    button type="submit" .px-2.border.border-red-700.rounded.hover:bg-neutral-700.cursor-pointer { "Usuń" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn totp_roles(required: &[Role], csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Wymagana weryfikacja dwuetapowa" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                form .flex.flex-col.gap-1 method="post" action="/panel/ustawienia/2fa/role" {
+                    (csrf)
+                    p.text-neutral-500.mb-2 {
+                        "Konta z zaznaczonymi rolami mogą korzystać z panelu dopiero po włączeniu weryfikacji dwuetapowej."
+                    }
+                    @for role in Role::ALL {
+                        label.flex.gap-2 {
+                            input type="checkbox" name=(role.as_str()) checked[required.contains(&role)];
+                            (role.display_name())
+                        }
+                    }
+                    button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Zapisz" }
+                }
+            }
+        }
+    }
+}
+
+fn new_user(csrf: &CsrfToken) -> Markup {
+    html! {
+        .mx-auto.max-w-3xl.p-4 {
+            p.font-serif.text-xl.ml-1 { "Nowe konto" }
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600 {
+                form .flex.flex-col.gap-1 method="post" action="/panel/ustawienia/konta" {
+                    (csrf)
+                    label for="handle" .mr-4 { "Login" }
+                    input name="handle" id="handle" required
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    label for="password" .mr-4 { "Hasło " span.text-neutral-500 { "(min. " (MIN_PASSWORD_LEN) " znaków)" } }
+                    input name="password" id="password" type="password" required minlength=(MIN_PASSWORD_LEN)
+                        .mb-3.py-1.px-2.border.border-neutral-600.rounded.bg-neutral-900;
+                    label for="role-new" .mr-4 { "Rola" }
+                    .mb-3.flex { (role_select("role-new", Role::Counter)) }
+                    button type="submit" .p-1.px-2.border.border-neutral-600.rounded.ml-auto { "Utwórz konto" }
+                }
+            }
+        }
+    }
+}
+
+fn role_select(id: &str, selected: Role) -> Markup {
+    html! {
+        select name="role" id=(id) .px-2.border.border-neutral-600.rounded.bg-neutral-900 {
+            @for role in Role::ALL {
+                option value=(role.as_str()) selected[role == selected] { (role.display_name()) }
+            }
+        }
+    }
+}
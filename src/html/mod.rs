@@ -1,5 +1,8 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Local, Utc};
 use maud::{DOCTYPE, Markup, html};
 
+pub mod charts;
 pub mod controls;
 pub mod stats;
 
@@ -15,10 +18,57 @@ pub fn head(title: &str) -> Markup {
     }
 }
 
-pub const JS_CLEAN_QUERY: &str = r#"
-if (window.location.search) {
-    history.replaceState({}, '', window.location.pathname);
+/// A moment as shown in the panel, in the server's time zone.
+pub fn local_time(at: DateTime<Utc>) -> String {
+    at.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
 }
+
+/// Page shown when a request fails, see [`crate::error::render_errors`].
+pub fn error_page(competition_name: &str, status: StatusCode, msg: &str) -> Markup {
+    html! {
+        (head(competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full;
+        .font-serif.flex.justify-between.max-w-3xl.mx-auto.p-4 {
+            a href="/" { p { (competition_name) } }
+            a href="/panel" { p { "Panel kontrolny" } }
+        }
+        .mx-auto.max-w-3xl.p-4 {
+            .w-full.p-4.bg-neutral-800.text-neutral-200.rounded.border.border-neutral-600.flex.flex-col.gap-3 {
+                p.font-serif.text-center.text-xl { "Coś poszło nie tak" }
+                .p-3.bg-red-900.bg-opacity-50.border.border-red-700.rounded.text-red-200 {
+                    p { (msg) }
+                }
+                p.text-center.text-neutral-500 { "Kod błędu: " (status.as_u16()) }
+                a href="/panel" .mx-auto.px-2.border.border-neutral-600.rounded.hover:bg-neutral-700 { "Wróć do panelu" }
+            }
+        }
+    }
+}
+
+pub const JS_LIVE_STATS: &str = r#"
+if (window.EventSource) {
+    const source = new EventSource('/api/stats/live');
+    source.addEventListener('stats', (e) => {
+        for (const [id, html] of Object.entries(JSON.parse(e.data))) {
+            const tile = document.getElementById(id);
+            if (tile) tile.innerHTML = html;
+        }
+    });
+}
+"#;
+/// Keeps a running total on the cash counting form; the server works out its own.
+pub const JS_CASH_TOTAL: &str = r#"
+const cashForm = document.getElementById('cash-count');
+const cashTotal = document.getElementById('cash-total');
+cashForm.addEventListener('input', () => {
+    let grosze = 0;
+    for (const input of cashForm.querySelectorAll('input[data-grosze]')) {
+        grosze += (parseInt(input.value, 10) || 0) * Number(input.dataset.grosze);
+    }
+    cashTotal.textContent = (grosze / 100).toLocaleString('pl-PL', { style: 'currency', currency: 'PLN' });
+});
 "#;
 pub const SVG_PACKAGE_OPEN: &str = include_str!("../lucideicons/package-open.svg");
 pub const SVG_SETTINGS: &str = include_str!("../lucideicons/settings.svg");
@@ -1,29 +1,167 @@
-use maud::{Markup, html};
+use std::collections::HashMap;
 
-use crate::html::head;
+use axum::{extract::State, http::HeaderMap, response::Response};
+use chrono::NaiveDate;
+use maud::{Markup, PreEscaped, html};
+use rusqlite::Connection;
+use uuid::Uuid;
 
-pub async fn stats() -> Markup {
-    html! {
-        (head("Zbiorywalizacja WPiK"))
+use crate::{
+    config::Config,
+    error::AppError,
+    html::{
+        JS_LIVE_STATS,
+        charts::{container_colors, lead_line_chart, totals_bar_chart},
+        head,
+    },
+    state::AppState,
+    stats::{Lead, StatsError, Summary, timeline},
+    users::User,
+};
+
+pub async fn stats(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    state.respond(move |conn| stats_page(conn, &headers)).await
+}
+
+fn stats_page(conn: &Connection, headers: &HeaderMap) -> Result<Markup, AppError> {
+    let config = Config::load(conn).map_err(AppError::read("config"))?;
+    if !stats_visible(&config, headers, conn) {
+        return Ok(stats_hidden(&config));
+    }
+    let [totals, lead, sum, timeline] = tiles(conn, &config).map_err(AppError::read("stats"))?;
+
+    Ok(html! {
+        (head(&config.competition_name))
         body.bg-neutral-900.text-neutral-300.lg:min-h-screen.w-full.flex.flex-col {
-            p.text-center.text-2xl.font-serif.py-4 { "Zbiorywalizacja WPiK" }
+            .py-4.text-center {
+                p.text-2xl.font-serif { (config.competition_name) }
+                @if let Some(dates) = campaign_dates(&config) {
+                    p.text-neutral-500 { (dates) }
+                }
+            }
             .pb-4.lg:pb-8.px-4.lg:px-8.w-full.grid.grid-cols-3.grid-rows-3.gap-3.flex-1 {
                 div class="bg-neutral-700 flex justify-center items-center border border-neutral-500 rounded row-span-2" {
-                    "Logo or something"
+                    @if let Some(url) = &config.logo_url {
+                        img.max-h-full.max-w-full.object-contain.p-4 src=(url) alt=(config.competition_name);
+                    } @else {
+                        p.p-4.text-center.text-2xl.font-serif { (config.competition_name) }
+                    }
                 }
-                div class="bg-neutral-700 flex justify-center items-center text-center border border-neutral-500 rounded row-span-2 col-span-2" {
-                    "Main graph (total amts in each container - a graph)"
+                @if let (id, Some(tile)) = totals {
+                    div id=(id) class="bg-neutral-700 flex justify-center items-center text-center border border-neutral-500 rounded row-span-2 col-span-2" {
+                        (tile)
+                    }
                 }
-                div class="bg-neutral-700 flex justify-center items-center border border-neutral-500 rounded" {
-                    "THIS CONTAINER is in the lead by THIS AMOUNT"
+                @if let (id, Some(tile)) = lead {
+                    div id=(id) class="bg-neutral-700 flex justify-center items-center text-center border border-neutral-500 rounded" {
+                        (tile)
+                    }
                 }
-                div class="bg-neutral-700 flex justify-center items-center border border-neutral-500 rounded" {
-                    "TOTAL AMOUNT OF DONATIONS and SUM OF DONATION AMOUNTS"
+                @if let (id, Some(tile)) = sum {
+                    div id=(id) class="bg-neutral-700 flex justify-center items-center text-center border border-neutral-500 rounded" {
+                        (tile)
+                    }
                 }
-                div class="bg-neutral-700 flex justify-center items-center border border-neutral-500 rounded" {
-                    "graph of lead over time"
+                @if let (id, Some(tile)) = timeline {
+                    div id=(id) class="bg-neutral-700 flex justify-center items-center text-center border border-neutral-500 rounded p-2" {
+                        (tile)
+                    }
                 }
             }
+            script { (PreEscaped(JS_LIVE_STATS)) }
+        }
+    })
+}
+
+/// Whether the stats may be shown to whoever sent the request: anyone while they're
+/// public, only logged in users otherwise.
+pub fn stats_visible(config: &Config, headers: &HeaderMap, conn: &Connection) -> bool {
+    config.stats_public || matches!(User::authenticate(headers, conn), Ok(Some(_)))
+}
+
+fn stats_hidden(config: &Config) -> Markup {
+    html! {
+        (head(&config.competition_name))
+        body.bg-neutral-900.text-neutral-300.min-h-screen.w-full.flex.flex-col.items-center.gap-4.py-4 {
+            p.text-2xl.font-serif { (config.competition_name) }
+            p { "Wyniki nie są obecnie publiczne." }
+            a href="/panel" .px-2.border.border-neutral-600.rounded.hover:bg-neutral-700 { "Zaloguj się" }
+        }
+    }
+}
+
+fn campaign_dates(config: &Config) -> Option<String> {
+    let date = |d: NaiveDate| d.format("%d.%m.%Y").to_string();
+    match (config.campaign_start, config.campaign_end) {
+        (Some(start), Some(end)) => Some(format!("{} – {}", date(start), date(end))),
+        (Some(start), None) => Some(format!("od {}", date(start))),
+        (None, Some(end)) => Some(format!("do {}", date(end))),
+        (None, None) => None,
+    }
+}
+
+/// Contents of every live-updated dashboard tile, paired with the tile's element id.
+/// Tiles the configuration hides have no contents.
+fn tiles(
+    conn: &Connection,
+    config: &Config,
+) -> Result<[(&'static str, Option<Markup>); 4], StatsError> {
+    let summary = Summary::load(conn)?;
+    let colors = container_colors(&summary.totals);
+    let timeline = match config.show_timeline {
+        true => Some(lead_line_chart(&timeline(conn)?, &colors)),
+        false => None,
+    };
+    Ok([
+        ("stats-totals", Some(stats_totals(&summary, &colors))),
+        ("stats-lead", Some(stats_lead(&summary))),
+        ("stats-sum", config.show_sum.then(|| stats_sum(&summary))),
+        ("stats-timeline", timeline),
+    ])
+}
+
+/// Dashboard tiles as a JSON object of element id to inner HTML, as sent over `/api/stats/live`.
+pub fn render_tiles(conn: &Connection) -> Result<String, StatsError> {
+    let tiles = tiles(conn, &Config::load(conn)?)?
+        .into_iter()
+        .filter_map(|(id, markup)| Some((id.to_owned(), markup?.into_string().into())))
+        .collect::<serde_json::Map<_, _>>();
+    Ok(serde_json::Value::Object(tiles).to_string())
+}
+
+fn stats_totals(summary: &Summary, colors: &HashMap<Uuid, &str>) -> Markup {
+    html! {
+        @if summary.totals.is_empty() {
+            p.text-xl { "Brak pojemników." }
+        } @else {
+            (totals_bar_chart(&summary.totals, colors))
+        }
+    }
+}
+
+fn stats_lead(summary: &Summary) -> Markup {
+    html! {
+        @match summary.lead() {
+            Lead::NoData => p { "Jeszcze nikt nie prowadzi." },
+            Lead::Tie => p { "Remis na prowadzeniu!" },
+            Lead::Leader(t, margin) => p {
+                span.text-xl.font-serif { (t.name) }
+                br;
+                "prowadzi z przewagą "
+                span.text-xl.font-serif { (margin) }
+            },
+        }
+    }
+}
+
+fn stats_sum(summary: &Summary) -> Markup {
+    html! {
+        p {
+            "Zebrano łącznie"
+            br;
+            span.text-2xl.font-serif { (summary.sum) }
+            br;
+            "w " (summary.count) @if summary.count == 1 { " datku" } @else { " datkach" }
         }
     }
 }
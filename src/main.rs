@@ -1,24 +1,48 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use axum::{
-    Router,
+    Router, middleware,
     routing::{get, post},
 };
 use tokio::net::TcpListener;
 
 use crate::{
     api::{css, hellaur},
-    database::db_check,
+    database::{db_check, open_pool},
     html::{
-        controls::{containers::controls_containers, controls},
+        controls::{
+            containers::controls_containers,
+            contributions::{controls_cash_count, controls_contribution, controls_contributions},
+            controls,
+            jobs::controls_jobs,
+            logs::controls_logs_page,
+            sessions::controls_sessions,
+            settings::controls_settings,
+        },
         stats::stats,
     },
+    state::AppState,
+    users::{
+        auth::{record_client_ip, refresh_session_cookie},
+        sessions::SessionPolicy,
+    },
 };
 
 mod api;
+mod audit;
+mod cash;
+mod config;
+mod containers;
+mod contributions;
 mod crypto;
 mod database;
+mod error;
+mod flash;
 mod html;
+mod money;
+mod scheduler;
+mod state;
+mod stats;
 mod users;
 
 const DEFAULT_PORT: u16 = 2025;
@@ -34,19 +58,126 @@ async fn main() -> Result<(), Box<dyn Error>> {
         },
     };
 
-    db_check()?;
+    SessionPolicy::init_from_env()?;
+    crypto::init_token_key()?;
+    let pool = open_pool()?;
+    db_check(&pool)?;
+    let state = AppState::new(pool);
+    scheduler::start(&state);
     let r = Router::new()
         .route("/", get(stats))
         .route("/panel", get(controls))
-        .route("/panel/pojemniki", get(controls_containers))
+        .route(
+            "/panel/datki",
+            get(controls_contributions).post(api::new_contribution_redir),
+        )
+        .route("/panel/datki/{id}", get(controls_contribution))
+        .route(
+            "/panel/datki/{id}/korekta",
+            post(api::correct_contribution_redir),
+        )
+        .route(
+            "/panel/datki/{id}/uniewaznij",
+            post(api::void_contribution_redir),
+        )
+        .route(
+            "/panel/datki/{id}/cofnij",
+            post(api::undo_contribution_redir),
+        )
+        .route("/panel/konfiguracja", post(api::save_config_redir))
+        .route(
+            "/panel/liczenie",
+            get(controls_cash_count).post(api::cash_count_redir),
+        )
+        .route(
+            "/panel/pojemniki",
+            get(controls_containers).post(api::new_container_redir),
+        )
+        .route(
+            "/panel/pojemniki/{id}/nazwa",
+            post(api::rename_container_redir),
+        )
+        .route(
+            "/panel/pojemniki/{id}/archiwum",
+            post(api::archive_container_redir),
+        )
+        .route(
+            "/panel/pojemniki/{id}/usun",
+            post(api::delete_container_redir),
+        )
+        .route("/panel/rejestr", get(controls_logs_page))
+        .route("/panel/zadania", get(controls_jobs))
+        .route("/panel/zadania/{name}/uruchom", post(api::run_job_redir))
+        .route("/panel/sesje", get(controls_sessions))
+        .route(
+            "/panel/sesje/pozostale",
+            post(api::revoke_other_sessions_redir),
+        )
+        .route(
+            "/panel/sesje/{id}/uniewaznij",
+            post(api::revoke_session_redir),
+        )
+        .route("/panel/ustawienia", get(controls_settings))
+        .route("/panel/ustawienia/haslo", post(api::change_password_redir))
+        .route("/panel/ustawienia/konta", post(api::new_user_redir))
+        .route("/panel/ustawienia/tokeny", post(api::new_api_token))
+        .route("/panel/ustawienia/2fa", post(api::begin_totp_redir))
+        .route("/panel/ustawienia/2fa/potwierdz", post(api::confirm_totp))
+        .route(
+            "/panel/ustawienia/2fa/kody",
+            post(api::regenerate_recovery_codes),
+        )
+        .route(
+            "/panel/ustawienia/2fa/wylacz",
+            post(api::disable_totp_redir),
+        )
+        .route("/panel/ustawienia/2fa/role", post(api::totp_roles_redir))
+        .route(
+            "/panel/ustawienia/konta/{id}/2fa/wylacz",
+            post(api::reset_user_totp_redir),
+        )
+        .route(
+            "/panel/ustawienia/tokeny/{id}/usun",
+            post(api::revoke_api_token_redir),
+        )
+        .route(
+            "/panel/ustawienia/konta/{id}/haslo",
+            post(api::reset_password_redir),
+        )
+        .route(
+            "/panel/ustawienia/konta/{id}/rola",
+            post(api::set_role_redir),
+        )
+        .route(
+            "/panel/ustawienia/konta/{id}/usun",
+            post(api::delete_user_redir),
+        )
         .route("/login", post(api::login_redir))
+        .route("/login/2fa", post(api::login_totp_redir))
         .route("/logout", post(api::logout_redir))
+        .route("/api/me", get(api::me))
+        .route("/api/stats", get(api::api_stats))
+        .route("/api/contributions", post(api::api_new_contribution))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            refresh_session_cookie,
+        ))
+        // routed past the layer above to spare it a database roundtrip per request:
+        // static files don't use the session, and the stats stream is held open
+        // rather than browsed
         .route("/live", get(hellaur))
         .route("/styles.css", get(css))
-        .route("/api/me", get(api::me));
+        .route("/api/stats/live", get(api::stats_live))
+        .layer(middleware::from_fn(flash::clear_flash))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error::render_errors,
+        ))
+        .layer(middleware::from_fn(record_client_ip))
+        .with_state(state);
     let l = TcpListener::bind(format!("0.0.0.0:{port}")).await?;
     println!("listening on {}", l.local_addr()?);
 
-    axum::serve(l, r).await?;
+    axum::serve(l, r.into_make_service_with_connect_info::<SocketAddr>()).await?;
     Ok(())
 }
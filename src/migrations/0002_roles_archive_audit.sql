@@ -0,0 +1,25 @@
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'counter'
+    CHECK (role IN ('infradmin', 'organizer', 'counter', 'viewer'));
+UPDATE users SET role = 'infradmin' WHERE id = 'ffffffff-ffff-ffff-ffff-ffffffffffff';
+
+ALTER TABLE containers ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE contributions ADD COLUMN recorded_by TEXT DEFAULT NULL REFERENCES users(id);
+ALTER TABLE contributions ADD COLUMN recorded_at INTEGER NOT NULL DEFAULT 0;
+-- the original time is unknown, so existing contributions date from the upgrade
+UPDATE contributions SET recorded_at = unixepoch();
+
+-- the old logs table was never written to
+DROP TABLE logs;
+CREATE TABLE logs (
+    id              TEXT NOT NULL UNIQUE PRIMARY KEY,
+    at              INTEGER NOT NULL,
+    actor           TEXT DEFAULT NULL REFERENCES users(id),
+    action          TEXT NOT NULL,
+    entity_type     TEXT DEFAULT NULL,
+    entity_id       TEXT DEFAULT NULL,
+    before          TEXT DEFAULT NULL, -- JSON snapshot
+    after           TEXT DEFAULT NULL  -- JSON snapshot
+);
+CREATE INDEX logs_entity ON logs (entity_id);
+CREATE INDEX logs_actor ON logs (actor);
@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN user_agent TEXT DEFAULT NULL;
+ALTER TABLE sessions ADD COLUMN ip TEXT DEFAULT NULL;
+CREATE INDEX sessions_user ON sessions (user_id);
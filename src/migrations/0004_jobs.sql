@@ -0,0 +1,15 @@
+CREATE TABLE jobs (
+    name            TEXT NOT NULL UNIQUE PRIMARY KEY,
+    last_started    INTEGER DEFAULT NULL,
+    last_finished   INTEGER DEFAULT NULL,
+    last_ok         INTEGER DEFAULT NULL,
+    last_message    TEXT DEFAULT NULL
+);
+
+CREATE TABLE stats_snapshots (
+    at              INTEGER NOT NULL,
+    container       TEXT NOT NULL REFERENCES containers(id),
+    total           INTEGER NOT NULL, -- in grosze
+    count           INTEGER NOT NULL
+);
+CREATE INDEX stats_snapshots_at ON stats_snapshots (at);
@@ -0,0 +1,4 @@
+-- sessions used to store their tokens in plain text; end them all,
+-- from now on only keyed hashes of the tokens are stored
+DELETE FROM sessions;
+ALTER TABLE sessions RENAME COLUMN token TO token_hash;
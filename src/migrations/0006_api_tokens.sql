@@ -0,0 +1,11 @@
+CREATE TABLE api_tokens (
+    id              TEXT NOT NULL UNIQUE PRIMARY KEY,
+    user_id         TEXT NOT NULL REFERENCES users(id),
+    name            TEXT NOT NULL,
+    token_hash      TEXT NOT NULL UNIQUE,
+    scopes          TEXT NOT NULL, -- space-separated, e.g. 'stats:read contributions:write'
+    created_at      INTEGER NOT NULL,
+    last_used       INTEGER DEFAULT NULL,
+    revoked         INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX api_tokens_user ON api_tokens (user_id);
@@ -0,0 +1,6 @@
+CREATE TABLE login_attempts (
+    key             TEXT NOT NULL UNIQUE PRIMARY KEY, -- 'account:<handle>' or 'ip:<address>'
+    failures        INTEGER NOT NULL,
+    last_failure    INTEGER NOT NULL,
+    locked_until    INTEGER DEFAULT NULL
+);
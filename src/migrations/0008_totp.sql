@@ -0,0 +1,19 @@
+ALTER TABLE users ADD COLUMN totp_secret TEXT DEFAULT NULL; -- RFC 4648 base32, set once confirmed
+ALTER TABLE users ADD COLUMN totp_pending TEXT DEFAULT NULL; -- secret shown but not confirmed yet
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER DEFAULT NULL; -- so a code can't be reused
+
+CREATE TABLE recovery_codes (
+    user_id         TEXT NOT NULL REFERENCES users(id),
+    code_hash       TEXT NOT NULL UNIQUE,
+    used_at         INTEGER DEFAULT NULL
+);
+CREATE INDEX recovery_codes_user ON recovery_codes (user_id);
+
+-- logins that got past the password and wait for the second factor
+CREATE TABLE login_challenges (
+    token_hash      TEXT NOT NULL UNIQUE PRIMARY KEY,
+    user_id         TEXT NOT NULL REFERENCES users(id),
+    expiry          INTEGER NOT NULL
+);
+
+ALTER TABLE config ADD COLUMN totp_required_roles TEXT NOT NULL DEFAULT ''; -- space-separated
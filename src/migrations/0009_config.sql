@@ -0,0 +1,7 @@
+ALTER TABLE config ADD COLUMN competition_name TEXT NOT NULL DEFAULT 'Zbiorywalizacja WPiK';
+ALTER TABLE config ADD COLUMN campaign_start TEXT DEFAULT NULL; -- YYYY-MM-DD
+ALTER TABLE config ADD COLUMN campaign_end TEXT DEFAULT NULL; -- YYYY-MM-DD, inclusive
+ALTER TABLE config ADD COLUMN stats_public INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE config ADD COLUMN show_sum INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE config ADD COLUMN show_timeline INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE config ADD COLUMN logo_url TEXT DEFAULT NULL;
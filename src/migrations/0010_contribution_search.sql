@@ -0,0 +1,23 @@
+-- full-text index over contribution notes, kept in step with the table by triggers;
+-- it stores the notes itself, as rowids of contributions aren't stable across VACUUM
+CREATE VIRTUAL TABLE contribution_notes USING fts5(
+    id UNINDEXED,
+    notes,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+INSERT INTO contribution_notes (id, notes)
+    SELECT id, notes FROM contributions WHERE notes IS NOT NULL;
+
+CREATE TRIGGER contribution_notes_insert AFTER INSERT ON contributions
+WHEN new.notes IS NOT NULL BEGIN
+    INSERT INTO contribution_notes (id, notes) VALUES (new.id, new.notes);
+END;
+CREATE TRIGGER contribution_notes_update AFTER UPDATE OF notes ON contributions BEGIN
+    DELETE FROM contribution_notes WHERE id = old.id;
+    INSERT INTO contribution_notes (id, notes) SELECT new.id, new.notes WHERE new.notes IS NOT NULL;
+END;
+CREATE TRIGGER contribution_notes_delete AFTER DELETE ON contributions BEGIN
+    DELETE FROM contribution_notes WHERE id = old.id;
+END;
+
+CREATE INDEX contributions_recorded_at ON contributions (recorded_at);
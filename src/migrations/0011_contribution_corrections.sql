@@ -0,0 +1,9 @@
+-- contributions are never deleted: a correction keeps the amount first recorded, and a
+-- voided contribution stays in the table but counts towards nothing
+ALTER TABLE contributions ADD COLUMN original_amount INTEGER DEFAULT NULL;
+ALTER TABLE contributions ADD COLUMN corrected_at INTEGER DEFAULT NULL;
+ALTER TABLE contributions ADD COLUMN corrected_by TEXT DEFAULT NULL REFERENCES users(id);
+ALTER TABLE contributions ADD COLUMN correction_reason TEXT DEFAULT NULL;
+ALTER TABLE contributions ADD COLUMN voided_at INTEGER DEFAULT NULL;
+ALTER TABLE contributions ADD COLUMN voided_by TEXT DEFAULT NULL REFERENCES users(id);
+ALTER TABLE contributions ADD COLUMN void_reason TEXT DEFAULT NULL;
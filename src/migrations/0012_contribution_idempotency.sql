@@ -0,0 +1,5 @@
+-- one-time key from the form a contribution was recorded with, so that a resubmitted
+-- form finds the contribution it already made instead of recording another
+ALTER TABLE contributions ADD COLUMN idempotency_key TEXT DEFAULT NULL;
+CREATE UNIQUE INDEX contributions_idempotency_key ON contributions (idempotency_key)
+    WHERE idempotency_key IS NOT NULL;
@@ -0,0 +1,3 @@
+-- coins and notes a contribution was counted from, as a JSON object of denominations
+-- in grosze to quantities; NULL for contributions typed in as a total
+ALTER TABLE contributions ADD COLUMN cash_count TEXT DEFAULT NULL;
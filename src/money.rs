@@ -0,0 +1,279 @@
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, Sub},
+    str::FromStr,
+};
+
+use rusqlite::{
+    ToSql,
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+};
+use serde::{Deserialize, Serialize};
+
+/// An amount of money in grosze. Stored as an INTEGER and sent over JSON as a plain
+/// number of grosze; typed in and shown in złoty, the Polish way.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct Money(i64);
+
+#[derive(thiserror::Error, Debug)]
+pub enum MoneyError {
+    #[error("Not an amount of money")]
+    Invalid,
+    #[error("Amount must not be negative")]
+    Negative,
+    #[error("Amount is too large")]
+    TooLarge,
+}
+impl MoneyError {
+    pub fn msg(&self) -> &'static str {
+        match self {
+            MoneyError::Invalid => "Nieprawidłowa kwota.",
+            MoneyError::Negative => "Kwota nie może być ujemna.",
+            MoneyError::TooLarge => "Kwota jest za duża.",
+        }
+    }
+}
+
+/// Characters accepted between groups of thousands: a space, a no-break space and
+/// the narrow no-break space some keyboards and spreadsheets produce.
+const GROUP_SEPARATORS: [char; 3] = [' ', '\u{a0}', '\u{202f}'];
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub fn grosze(self) -> i64 {
+        self.0
+    }
+    /// The amount as it's prefilled in a form field, e.g. "1234,50": no grouping and
+    /// no currency, so that it parses back as it is.
+    pub fn to_input(self) -> String {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let grosze = self.0.unsigned_abs();
+        format!("{sign}{},{:02}", grosze / 100, grosze % 100)
+    }
+}
+
+/// Parses an amount typed in złoty, e.g. "5", "12,5", "12.50 zł" or "1 234,56". Digits
+/// may be grouped in threes, and at most two decimals are allowed.
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(input: &str) -> Result<Money, MoneyError> {
+        let input = input.trim();
+        let input = input
+            .strip_suffix("zł")
+            .or_else(|| input.strip_suffix("zl"))
+            .unwrap_or(input)
+            .trim_end();
+        if input.starts_with('-') {
+            return Err(MoneyError::Negative);
+        }
+        let (whole, frac) = match input.split_once([',', '.']) {
+            Some((_, "")) => return Err(MoneyError::Invalid),
+            Some((w, f)) => (w, f),
+            None => (input, ""),
+        };
+        let mut groups = whole.split(GROUP_SEPARATORS);
+        let first = groups.next().unwrap_or_default();
+        let rest = groups.collect::<Vec<_>>();
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !digits(first)
+            || !rest.iter().all(|g| g.len() == 3 && digits(g))
+            || (!rest.is_empty() && first.len() > 3)
+            || !(frac.is_empty() || (frac.len() <= 2 && digits(frac)))
+        {
+            return Err(MoneyError::Invalid);
+        }
+
+        let whole = std::iter::once(first)
+            .chain(rest)
+            .flat_map(str::bytes)
+            .try_fold(0i64, |n, b| {
+                n.checked_mul(10)?.checked_add(i64::from(b - b'0'))
+            })
+            .ok_or(MoneyError::TooLarge)?;
+        let frac = match frac.len() {
+            0 => 0,
+            1 => frac.parse::<i64>().map_err(|_| MoneyError::Invalid)? * 10,
+            _ => frac.parse::<i64>().map_err(|_| MoneyError::Invalid)?,
+        };
+        whole
+            .checked_mul(100)
+            .and_then(|w| w.checked_add(frac))
+            .map(Money)
+            .ok_or(MoneyError::TooLarge)
+    }
+}
+
+/// Formats the amount the Polish way, e.g. "1 234,56 zł".
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let grosze = self.0.unsigned_abs();
+        write!(
+            f,
+            "{sign}{},{:02}\u{a0}zł",
+            group_thousands(grosze / 100),
+            grosze % 100
+        )
+    }
+}
+
+/// Group digits in threes with non-breaking spaces, e.g. "1 234 567".
+pub fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push('\u{a0}');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// For amounts coming in over JSON, which must not be negative.
+impl TryFrom<i64> for Money {
+    type Error = MoneyError;
+    fn try_from(grosze: i64) -> Result<Money, MoneyError> {
+        match grosze {
+            g if g < 0 => Err(MoneyError::Negative),
+            g => Ok(Money(g)),
+        }
+    }
+}
+
+impl From<Money> for i64 {
+    fn from(money: Money) -> i64 {
+        money.0
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Money)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<i64, MoneyError> {
+        input.parse::<Money>().map(Money::grosze)
+    }
+
+    #[test]
+    fn parses_decimals_with_comma_or_dot() {
+        assert_eq!(parse("5").unwrap(), 500);
+        assert_eq!(parse("12,5").unwrap(), 1250);
+        assert_eq!(parse("12.50").unwrap(), 1250);
+        assert_eq!(parse("0,07").unwrap(), 7);
+        assert_eq!(parse(" 3,20 zł ").unwrap(), 320);
+        assert_eq!(parse("3zl").unwrap(), 300);
+    }
+
+    #[test]
+    fn parses_thousands_groups() {
+        assert_eq!(parse("1 234,56").unwrap(), 123456);
+        assert_eq!(parse("1\u{a0}234\u{a0}567").unwrap(), 123456700);
+        assert_eq!(parse("12\u{202f}345,6").unwrap(), 1234560);
+        assert!(matches!(parse("1 23"), Err(MoneyError::Invalid)));
+        assert!(matches!(parse("1234 567"), Err(MoneyError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_dot_as_thousands_separator() {
+        assert!(matches!(parse("1.234,56"), Err(MoneyError::Invalid)));
+        assert!(matches!(parse("1,234.56"), Err(MoneyError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_more_than_two_decimals() {
+        assert!(matches!(parse("1,234"), Err(MoneyError::Invalid)));
+        assert!(matches!(parse("0.001"), Err(MoneyError::Invalid)));
+        assert!(matches!(parse("5,"), Err(MoneyError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for input in ["", "zł", "abc", "1e3", "+5", "5,-1", ",5"] {
+            assert!(matches!(parse(input), Err(MoneyError::Invalid)), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_negatives() {
+        assert!(matches!(parse("-5"), Err(MoneyError::Negative)));
+        assert!(matches!(parse(" -0,50 zł"), Err(MoneyError::Negative)));
+        assert!(matches!(Money::try_from(-1), Err(MoneyError::Negative)));
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        assert_eq!(parse("92233720368547758,07").unwrap(), i64::MAX);
+        assert!(matches!(
+            parse("92233720368547758,08"),
+            Err(MoneyError::TooLarge)
+        ));
+        assert!(matches!(
+            parse("92233720368547759"),
+            Err(MoneyError::TooLarge)
+        ));
+        assert!(matches!(
+            parse("99999999999999999999"),
+            Err(MoneyError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn formats_the_polish_way() {
+        let money = |grosze: i64| Money::try_from(grosze).unwrap();
+        assert_eq!(money(0).to_string(), "0,00\u{a0}zł");
+        assert_eq!(money(5).to_string(), "0,05\u{a0}zł");
+        assert_eq!(money(123456).to_string(), "1\u{a0}234,56\u{a0}zł");
+        assert_eq!(
+            money(100000000).to_string(),
+            "1\u{a0}000\u{a0}000,00\u{a0}zł"
+        );
+        assert_eq!(money(123456).to_input(), "1234,56");
+    }
+
+    #[test]
+    fn formatted_input_parses_back() {
+        for grosze in [0, 1, 99, 100, 123456, 9876543210] {
+            let money = Money::try_from(grosze).unwrap();
+            assert_eq!(money.to_input().parse::<Money>().unwrap(), money);
+        }
+    }
+}
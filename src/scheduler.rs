@@ -0,0 +1,271 @@
+use std::{error::Error, sync::Mutex};
+
+use chrono::{DateTime, Days, Duration, Local, NaiveTime, TimeZone, Utc};
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{
+    database::backup,
+    state::AppState,
+    stats::{snapshot_totals, write_report},
+    users::{sessions::Session, throttle, totp},
+};
+
+type JobResult = Result<String, Box<dyn Error + Send + Sync>>;
+
+/// How long to wait before trying again when a job's status can't be read or it
+/// couldn't run, e.g. because the database was locked.
+const RETRY_DELAY: Duration = Duration::minutes(1);
+
+pub enum Schedule {
+    /// Runs this long after the previous run started.
+    Every(Duration),
+    /// Runs once a day at this local time. A run missed while the server was
+    /// down happens as soon as it starts again.
+    DailyAt(NaiveTime),
+}
+
+impl Schedule {
+    /// When the job is next due at `now`, given when it last started.
+    fn next_run(&self, last: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Schedule::Every(interval) => last.map(|l| l + *interval).unwrap_or(now),
+            Schedule::DailyAt(time) => {
+                let after = last.unwrap_or(now).with_timezone(&Local);
+                let day = after.date_naive();
+                [day, day + Days::new(1)]
+                    .into_iter()
+                    .filter_map(|d| Local.from_local_datetime(&d.and_time(*time)).earliest())
+                    .find(|at| *at > after)
+                    .map(|at| at.with_timezone(&Utc))
+                    .unwrap_or(now + Duration::days(1))
+            }
+        }
+    }
+    pub fn describe(&self) -> String {
+        match self {
+            Schedule::Every(interval) if interval.num_hours() > 0 => {
+                format!("co {} godz.", interval.num_hours())
+            }
+            Schedule::Every(interval) => format!("co {} min", interval.num_minutes()),
+            Schedule::DailyAt(time) => format!("codziennie o {}", time.format("%H:%M")),
+        }
+    }
+}
+
+pub struct Job {
+    pub name: &'static str,
+    pub display_name: &'static str,
+    pub schedule: Schedule,
+    /// Does the job's work, returning a short summary for the status page.
+    run: fn(&Connection) -> JobResult,
+}
+
+pub static JOBS: [Job; 4] = [
+    Job {
+        name: "session_cleanup",
+        display_name: "Usuwanie zakończonych sesji i prób logowania",
+        schedule: Schedule::Every(Duration::hours(6)),
+        run: |conn| {
+            Ok(format!(
+                "Usunięto sesji: {}, liczników logowań: {}, oczekujących logowań: {}.",
+                Session::delete_stale(conn)?,
+                throttle::delete_stale(conn)?,
+                totp::delete_expired_challenges(conn)?
+            ))
+        },
+    },
+    Job {
+        name: "stats_snapshot",
+        display_name: "Zapis stanu pojemników",
+        schedule: Schedule::Every(Duration::minutes(15)),
+        run: |conn| Ok(format!("Zapisano pojemników: {}.", snapshot_totals(conn)?)),
+    },
+    Job {
+        name: "backup",
+        display_name: "Kopia zapasowa bazy danych",
+        schedule: Schedule::DailyAt(NaiveTime::from_hms_opt(3, 0, 0).unwrap()),
+        run: |conn| Ok(format!("Zapisano {}.", backup(conn)?.display())),
+    },
+    Job {
+        name: "daily_report",
+        display_name: "Raport dzienny",
+        schedule: Schedule::DailyAt(NaiveTime::from_hms_opt(23, 55, 0).unwrap()),
+        run: |conn| Ok(format!("Zapisano {}.", write_report(conn)?.display())),
+    },
+];
+
+/// Names of the jobs running right now, so a job is never run twice at once.
+static RUNNING: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+pub fn find(name: &str) -> Option<&'static Job> {
+    JOBS.iter().find(|j| j.name == name)
+}
+
+/// Spawns a background task per registered job, running it whenever it is due.
+pub fn start(state: &AppState) {
+    for job in &JOBS {
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let last = state
+                    .with_db(|conn| JobStatus::load(job, conn).map(|s| s.last_started))
+                    .await;
+                let next = match last {
+                    Ok(Ok(last)) => job.schedule.next_run(last, Utc::now()),
+                    _ => Utc::now() + RETRY_DELAY,
+                };
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                if let Err(e) = run(&state, job).await {
+                    eprintln!("job {} failed: {e}", job.name);
+                    // its start may not have been recorded, leaving it due right away
+                    tokio::time::sleep(RETRY_DELAY.to_std().unwrap_or_default()).await;
+                }
+            }
+        });
+    }
+}
+
+/// Runs a job right away on the database thread pool and records its outcome.
+/// Returns the job's summary, or why it failed or couldn't start.
+pub async fn run(state: &AppState, job: &'static Job) -> Result<String, String> {
+    {
+        let mut running = RUNNING.lock().unwrap();
+        if running.contains(&job.name) {
+            return Err("Zadanie już trwa.".to_owned());
+        }
+        running.push(job.name);
+    }
+    let result = state
+        .with_db(move |conn| {
+            record_start(job, conn)?;
+            let outcome = (job.run)(conn).map_err(|e| e.to_string());
+            record_finish(job, &outcome, conn)?;
+            Ok::<_, rusqlite::Error>(outcome)
+        })
+        .await;
+    RUNNING.lock().unwrap().retain(|n| *n != job.name);
+    match result {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn record_start(job: &Job, conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare(
+        "INSERT INTO jobs (name, last_started) VALUES (?1, ?2)
+         ON CONFLICT (name) DO UPDATE SET last_started = excluded.last_started",
+    )?
+    .execute((job.name, Utc::now().timestamp()))?;
+    Ok(())
+}
+
+fn record_finish(
+    job: &Job,
+    outcome: &Result<String, String>,
+    conn: &Connection,
+) -> Result<(), rusqlite::Error> {
+    let (ok, message) = match outcome {
+        Ok(m) => (true, m),
+        Err(m) => (false, m),
+    };
+    conn.prepare(
+        "UPDATE jobs SET last_finished = ?2, last_ok = ?3, last_message = ?4 WHERE name = ?1",
+    )?
+    .execute((job.name, Utc::now().timestamp(), ok, message))?;
+    Ok(())
+}
+
+/// A job along with what happened the last time it ran.
+pub struct JobStatus {
+    pub job: &'static Job,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    pub last_ok: Option<bool>,
+    pub last_message: Option<String>,
+}
+
+impl JobStatus {
+    fn load(job: &'static Job, conn: &Connection) -> Result<JobStatus, rusqlite::Error> {
+        let row = conn
+            .prepare(
+                "SELECT last_started, last_finished, last_ok, last_message
+                 FROM jobs WHERE name = ?1",
+            )?
+            .query_one([job.name], |r| {
+                Ok((
+                    r.get::<_, Option<i64>>(0)?,
+                    r.get::<_, Option<i64>>(1)?,
+                    r.get::<_, Option<bool>>(2)?,
+                    r.get::<_, Option<String>>(3)?,
+                ))
+            })
+            .optional()?
+            .unwrap_or_default();
+        Ok(JobStatus {
+            job,
+            last_started: row.0.and_then(|t| DateTime::from_timestamp(t, 0)),
+            last_finished: row.1.and_then(|t| DateTime::from_timestamp(t, 0)),
+            last_ok: row.2,
+            last_message: row.3,
+        })
+    }
+    pub fn load_all(conn: &Connection) -> Result<Vec<JobStatus>, rusqlite::Error> {
+        JOBS.iter().map(|job| JobStatus::load(job, conn)).collect()
+    }
+    pub fn is_running(&self) -> bool {
+        RUNNING.lock().unwrap().contains(&self.job.name)
+    }
+    pub fn next_run(&self) -> DateTime<Utc> {
+        self.job.schedule.next_run(self.last_started, Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(y, m, d, h, min, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn every_runs_an_interval_after_the_last_start() {
+        let every = Schedule::Every(Duration::minutes(15));
+        let now = local(2026, 6, 10, 12, 0);
+        assert_eq!(every.next_run(None, now), now);
+        assert_eq!(
+            every.next_run(Some(local(2026, 6, 10, 11, 50)), now),
+            local(2026, 6, 10, 12, 5)
+        );
+        // overdue runs are due in the past, so they happen right away
+        assert_eq!(
+            every.next_run(Some(local(2026, 6, 9, 8, 0)), now),
+            local(2026, 6, 9, 8, 15)
+        );
+    }
+
+    #[test]
+    fn daily_runs_at_the_next_occurrence_of_its_time() {
+        let daily = Schedule::DailyAt(NaiveTime::from_hms_opt(3, 0, 0).unwrap());
+        let now = local(2026, 6, 10, 12, 0);
+        assert_eq!(daily.next_run(None, now), local(2026, 6, 11, 3, 0));
+        assert_eq!(
+            daily.next_run(Some(local(2026, 6, 10, 3, 0)), now),
+            local(2026, 6, 11, 3, 0)
+        );
+        assert_eq!(
+            daily.next_run(Some(local(2026, 6, 10, 1, 0)), now),
+            local(2026, 6, 10, 3, 0)
+        );
+        // a run missed while the server was down is due right away
+        assert_eq!(
+            daily.next_run(Some(local(2026, 6, 7, 3, 0)), now),
+            local(2026, 6, 8, 3, 0)
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use axum::response::{IntoResponse, Response};
+use rusqlite::Connection;
+use tokio::{sync::broadcast, task::JoinError};
+
+use crate::{database::DbPool, error::AppError, html::stats::render_tiles};
+
+/// How many stats updates a slow SSE subscriber may fall behind before skipping ahead.
+/// Every update carries the full dashboard, so skipping is harmless.
+const STATS_CHANNEL_CAPACITY: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DbError {
+    #[error("Couldn't get a database connection: {0}")]
+    PoolError(#[from] r2d2::Error),
+    #[error("Database task failed: {0}")]
+    TaskError(#[from] JoinError),
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    db: DbPool,
+    stats: broadcast::Sender<Arc<str>>,
+}
+
+impl AppState {
+    pub fn new(db: DbPool) -> AppState {
+        let (stats, _) = broadcast::channel(STATS_CHANNEL_CAPACITY);
+        AppState { db, stats }
+    }
+
+    /// Runs `f` on the blocking thread pool with a pooled connection. All SQLite
+    /// queries and password hashing go through here to keep the async workers free.
+    pub async fn with_db<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> T + Send + 'static,
+    ) -> Result<T, DbError> {
+        let pool = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            Ok(f(&conn))
+        })
+        .await
+        .map_err(DbError::from)?
+    }
+
+    /// [`AppState::with_db`] for page handlers, answering with an [`AppError`] if no connection is available.
+    pub async fn respond<R: IntoResponse + Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> R + Send + 'static,
+    ) -> Response {
+        match self.with_db(f).await {
+            Ok(response) => response.into_response(),
+            Err(e) => AppError::from(e).into_response(),
+        }
+    }
+
+    pub fn subscribe_stats(&self) -> broadcast::Receiver<Arc<str>> {
+        self.stats.subscribe()
+    }
+
+    /// Renders the stats dashboard tiles once and pushes them to every live viewer.
+    /// Call once a write that changes what the public stats page shows is committed,
+    /// so that viewers never see a change that gets rolled back.
+    pub fn publish_stats(&self, conn: &Connection) {
+        if self.stats.receiver_count() == 0 {
+            return;
+        }
+        match render_tiles(conn) {
+            // sending only fails when nobody is listening, which is fine
+            Ok(tiles) => _ = self.stats.send(tiles.into()),
+            Err(e) => eprintln!("failed to render stats update: {e}"),
+        }
+    }
+}
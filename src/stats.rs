@@ -0,0 +1,161 @@
+use std::{error::Error, fs, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Local, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{config::ConfigError, database::data_dir, money::Money};
+
+#[derive(Debug, Serialize)]
+pub struct ContainerTotal {
+    pub id: Uuid,
+    pub name: String,
+    pub total: Money,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// Per-container totals, highest first. Archived containers are included.
+    pub totals: Vec<ContainerTotal>,
+    pub count: u64,
+    pub sum: Money,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StatsError {
+    #[error("Failed to execute SQL: {0}")]
+    StatsSqlError(#[from] rusqlite::Error),
+    #[error("Non-UUID Container PK found in DB")]
+    NonUuidContainerId,
+    #[error("Failed to load config: {0}")]
+    StatsConfigError(#[from] ConfigError),
+}
+
+/// A single contribution as seen by the charts: when, where and how much.
+#[derive(Debug, Serialize)]
+pub struct TimelinePoint {
+    pub at: DateTime<Utc>,
+    pub container: Uuid,
+    pub amount: Money,
+}
+
+pub enum Lead<'a> {
+    /// Nothing has been donated yet.
+    NoData,
+    /// The top containers are exactly level.
+    Tie,
+    Leader(&'a ContainerTotal, Money),
+}
+
+impl Summary {
+    pub fn load(conn: &Connection) -> Result<Summary, StatsError> {
+        const QUERY: &str = "
+            SELECT c.id, c.name, COALESCE(SUM(d.amount), 0), COUNT(d.id)
+            FROM containers c
+            LEFT JOIN contributions d ON d.container = c.id AND d.voided_at IS NULL
+            GROUP BY c.id
+            ORDER BY 3 DESC, c.name
+        ";
+        let rows = conn
+            .prepare(QUERY)?
+            .query_map([], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, Money>(2)?,
+                    r.get::<_, u64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let totals = rows
+            .into_iter()
+            .map(|(id, name, total, count)| {
+                Ok(ContainerTotal {
+                    id: Uuid::from_str(&id).map_err(|_| StatsError::NonUuidContainerId)?,
+                    name,
+                    total,
+                    count,
+                })
+            })
+            .collect::<Result<Vec<_>, StatsError>>()?;
+
+        Ok(Summary {
+            count: totals.iter().map(|t| t.count).sum(),
+            sum: totals.iter().map(|t| t.total).sum(),
+            totals,
+        })
+    }
+
+    pub fn lead(&self) -> Lead<'_> {
+        match self.totals.as_slice() {
+            [] => Lead::NoData,
+            [first, ..] if first.total == Money::ZERO => Lead::NoData,
+            [first] => Lead::Leader(first, first.total),
+            [first, second, ..] if first.total == second.total => Lead::Tie,
+            [first, second, ..] => Lead::Leader(first, first.total - second.total),
+        }
+    }
+}
+
+/// All contributions that weren't voided, in the order they were recorded.
+pub fn timeline(conn: &Connection) -> Result<Vec<TimelinePoint>, StatsError> {
+    const QUERY: &str = "
+        SELECT recorded_at, container, amount
+        FROM contributions
+        WHERE container IS NOT NULL AND voided_at IS NULL
+        ORDER BY recorded_at, id
+    ";
+    let rows = conn
+        .prepare(QUERY)?
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, Money>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|(at, container, amount)| {
+            Ok(TimelinePoint {
+                at: DateTime::from_timestamp(at, 0).unwrap_or_default(),
+                container: Uuid::from_str(&container)
+                    .map_err(|_| StatsError::NonUuidContainerId)?,
+                amount,
+            })
+        })
+        .collect()
+}
+
+/// Stores the current per-container totals in `stats_snapshots`, for looking back at
+/// how the competition stood at a given time. Returns how many rows were written.
+pub fn snapshot_totals(conn: &Connection) -> Result<usize, StatsError> {
+    let summary = Summary::load(conn)?;
+    let at = Utc::now().timestamp();
+    let mut stmt = conn.prepare(
+        "INSERT INTO stats_snapshots (at, container, total, count) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for t in &summary.totals {
+        stmt.execute((at, t.id.to_string(), t.total, t.count))?;
+    }
+    Ok(summary.totals.len())
+}
+
+/// Writes the current standings to `reports/raport-<date>.csv` next to the database,
+/// overwriting the day's earlier report. Returns the report's path.
+pub fn write_report(conn: &Connection) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let summary = Summary::load(conn)?;
+    let dir = data_dir().join("reports");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("raport-{}.csv", Local::now().format("%Y-%m-%d")));
+
+    let mut csv = String::from("pojemnik;datki;suma\n");
+    for t in &summary.totals {
+        csv += &format!("{};{};{}\n", t.name.replace(';', ","), t.count, t.total);
+    }
+    csv += &format!("razem;{};{}\n", summary.count, summary.sum);
+    fs::write(&path, csv)?;
+    Ok(path)
+}
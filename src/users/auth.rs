@@ -1,15 +1,29 @@
-use axum::http::{
-    HeaderMap, StatusCode,
-    header::{AUTHORIZATION, COOKIE},
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+    },
+    middleware::Next,
+    response::Response,
 };
 use base64::prelude::*;
-use rusqlite::Connection;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
 
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::OnceLock;
 use uuid::Uuid;
 
-use crate::users::pwd::verify_password;
+use crate::audit::{self, Action};
+use crate::state::AppState;
+use crate::users::pwd::{hash_password, verify_password};
 use crate::users::sessions::{Session, SessionStructError};
+use crate::users::throttle::{self, Subject};
+use crate::users::tokens::{ApiToken, ApiTokenError, TOKEN_PREFIX};
+use crate::users::totp::{self, TotpError};
 use crate::users::{User, UserStructError};
 
 pub const COOKIE_NAME: &str = "wpikzbiorauth";
@@ -22,6 +36,18 @@ pub const COOKIE_CLEAR: &str = concat!(
 pub enum AuthError {
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("Wrong handle or password")]
+    WrongPassword,
+    #[error("Insufficient permissions")]
+    Forbidden,
+    #[error("Too many failed login attempts, locked until {0}")]
+    TooManyAttempts(DateTime<Utc>),
+    #[error("Failed to verify password")]
+    PasswordVerification,
+    #[error("Account uses two-factor authentication")]
+    SecondFactorRequired,
+    #[error("Two-factor authentication error: {0}")]
+    TotpError(#[from] TotpError),
     #[error("Session error: {0}")]
     SessionError(#[from] SessionStructError),
     #[error("User error: {0}")]
@@ -32,6 +58,8 @@ pub enum AuthError {
     InvalidBase64(#[from] base64::DecodeError),
     #[error("Invalid UTF-8 in credentials")]
     InvalidUtf8(#[from] std::string::FromUtf8Error),
+    #[error("API token error: {0}")]
+    TokenError(#[from] ApiTokenError),
     #[error("Database error: {0}")]
     DatabaseError(#[from] rusqlite::Error),
 }
@@ -39,8 +67,16 @@ impl AuthError {
     pub fn status_code(&self) -> StatusCode {
         use AuthError as AE;
         match self {
-            AE::InvalidCredentials => StatusCode::UNAUTHORIZED,
-            AE::SessionError(_) | AE::UserError(_) | AE::DatabaseError(_) => {
+            AE::InvalidCredentials | AE::WrongPassword => StatusCode::UNAUTHORIZED,
+            AE::Forbidden => StatusCode::FORBIDDEN,
+            AE::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            AE::PasswordVerification => StatusCode::INTERNAL_SERVER_ERROR,
+            AE::SecondFactorRequired => StatusCode::UNAUTHORIZED,
+            AE::TotpError(TotpError::TotpSqlError(_) | TotpError::MalformedSecret) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AE::TotpError(_) => StatusCode::UNAUTHORIZED,
+            AE::SessionError(_) | AE::UserError(_) | AE::TokenError(_) | AE::DatabaseError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
             AE::InvalidFormat | AE::InvalidUtf8(_) | AE::InvalidBase64(_) => {
@@ -48,11 +84,21 @@ impl AuthError {
             }
         }
     }
-    pub fn msg(&self) -> &str {
+    pub fn msg(&self) -> &'static str {
         use AuthError as AE;
         match self {
             AE::InvalidCredentials => "Twoja sesja wygasła lub jest niepoprawna. Spróbuj ponownie.",
-            AE::SessionError(_) | AE::UserError(_) | AE::DatabaseError(_) => {
+            AE::WrongPassword => "Nieprawidłowy login lub hasło.",
+            AE::Forbidden => "Nie masz uprawnień do tej operacji.",
+            AE::TooManyAttempts(_) => {
+                "Zbyt wiele nieudanych prób logowania. Spróbuj ponownie za kilka minut."
+            }
+            AE::PasswordVerification => "Błąd weryfikacji hasła.",
+            AE::SecondFactorRequired => {
+                "To konto używa weryfikacji dwuetapowej. Zaloguj się przez panel lub użyj tokenu API."
+            }
+            AE::TotpError(e) => e.msg(),
+            AE::SessionError(_) | AE::UserError(_) | AE::TokenError(_) | AE::DatabaseError(_) => {
                 "Błąd serwera. Skontaktuj się z webmasterem."
             }
             AE::InvalidFormat | AE::InvalidBase64(_) | AE::InvalidUtf8(_) => {
@@ -62,6 +108,83 @@ impl AuthError {
     }
 }
 
+/// `Set-Cookie` value storing a session token until the session's expiry.
+pub fn session_cookie(token: &str, expiry: DateTime<Utc>) -> String {
+    let secure = match cfg!(debug_assertions) {
+        false => "; Secure",
+        true => "",
+    };
+    format!(
+        "{COOKIE_NAME}={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        (expiry - Utc::now()).num_seconds().max(0),
+        secure
+    )
+}
+
+/// Carries the address a request came from to [`User::authenticate`], which only gets
+/// to see headers. Set by [`record_client_ip`], never by the client.
+const CLIENT_IP: HeaderName = HeaderName::from_static("x-wpik-client-ip");
+
+/// Middleware putting the peer's address in the [`CLIENT_IP`] header, replacing
+/// whatever the client sent under that name.
+pub async fn record_client_ip(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let ip = HeaderValue::from_str(&addr.ip().to_string()).expect("an IP is a valid header");
+    request.headers_mut().insert(CLIENT_IP, ip);
+    next.run(request).await
+}
+
+/// Middleware keeping the auth cookie's `Max-Age` in step with the session's sliding
+/// expiry, which handlers push forward whenever they authenticate a request.
+pub async fn refresh_session_cookie(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = cookie_token(request.headers()).map(str::to_owned);
+    let mut response = next.run(request).await;
+    let Some(token) = token else {
+        return response;
+    };
+    // leave responses that log in or out alone
+    if response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|c| c.as_bytes().starts_with(COOKIE_NAME.as_bytes()))
+    {
+        return response;
+    }
+    let cookie = state
+        .with_db(move |conn| {
+            let session = Session::get_by_token(&token, conn).ok()?;
+            match session.is_expired_or_revoked() {
+                true => None,
+                false => Some(session_cookie(&token, session.expiry())),
+            }
+        })
+        .await;
+    if let Ok(Some(cookie)) = cookie
+        && let Ok(cookie) = HeaderValue::from_str(&cookie)
+    {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    response
+}
+
+/// The session token from the auth cookie, if the request carries one.
+pub fn cookie_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+}
+
 enum AuthScheme<'a> {
     Basic(&'a str),
     Bearer(&'a str),
@@ -95,15 +218,8 @@ impl User {
                 auth_values.push(s.to_string());
             }
         }
-        for cookie_header in headers.get_all(COOKIE).iter() {
-            if let Ok(cookies) = cookie_header.to_str() {
-                for cookie in cookies.split(';') {
-                    let cookie = cookie.trim();
-                    if let Some(value) = cookie.strip_prefix(&format!("{}=", COOKIE_NAME)) {
-                        auth_values.push(format!("Bearer {}", value));
-                    }
-                }
-            }
+        if let Some(token) = cookie_token(headers) {
+            auth_values.push(format!("Bearer {}", token));
         }
 
         let mut basic_auth: Option<&str> = None;
@@ -126,15 +242,105 @@ impl User {
             }
         }
 
-        match (basic_auth, bearer_auth) {
-            (Some(credentials), _) => authenticate_basic(credentials, conn),
-            (None, Some(token)) => authenticate_bearer(token, conn),
-            (None, None) => Ok(None),
+        let user = match (basic_auth, bearer_auth) {
+            (Some(credentials), _) => {
+                let ip = headers.get(CLIENT_IP).and_then(|ip| ip.to_str().ok());
+                authenticate_basic(credentials, ip, conn)?
+            }
+            (None, Some(token)) => authenticate_bearer(token, conn)?,
+            (None, None) => None,
+        };
+        match user {
+            Some(mut user) => {
+                user.needs_totp = totp::is_missing(&user, conn)?;
+                Ok(Some(user))
+            }
+            None => Ok(None),
         }
     }
 }
 
-fn authenticate_basic(credentials: &str, conn: &Connection) -> Result<Option<User>, AuthError> {
+/// Hash of a random password, checked against for unknown handles so that they take
+/// as long to reject as a wrong password does.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        hash_password(&crate::crypto::generate_long_token()).expect("hashing a password failed")
+    })
+}
+
+/// Checks a handle and password, returning the account's id. Failures are counted per
+/// handle and, when known, per IP address, locking either out for a while once it has
+/// failed too often, and every failed attempt is written to the audit log. The handle's
+/// count is only reset once the login is complete, so for accounts with a second factor
+/// that's left to whoever checks it.
+pub fn check_credentials(
+    handle: &str,
+    password: &str,
+    ip: Option<&str>,
+    conn: &Connection,
+) -> Result<Uuid, AuthError> {
+    let mut subjects = vec![Subject::Account(handle)];
+    if let Some(ip) = ip {
+        subjects.push(Subject::Ip(ip));
+    }
+    if let Some(until) = throttle::locked_until(&subjects, conn)? {
+        log_failed_login(conn, None, handle, ip, "locked");
+        return Err(AuthError::TooManyAttempts(until));
+    }
+
+    let account = conn
+        .prepare("SELECT id, passhash FROM users WHERE handle = ?1")?
+        .query_row([handle], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .optional()?;
+    let passhash = account.as_ref().map_or(dummy_hash(), |(_, hash)| hash);
+    let valid = verify_password(password, passhash).map_err(|_| AuthError::PasswordVerification)?;
+
+    match account {
+        Some((id_str, _)) if valid => {
+            let id = Uuid::from_str(&id_str).map_err(|_| UserStructError::NonUuidPrimaryKey)?;
+            if !totp::is_enabled(&id, conn)? {
+                throttle::clear(&Subject::Account(handle), conn)?;
+            }
+            Ok(id)
+        }
+        account => {
+            throttle::record_failure(&subjects, conn)?;
+            let user_id = account.and_then(|(id, _)| Uuid::from_str(&id).ok());
+            log_failed_login(conn, user_id.as_ref(), handle, ip, "invalid");
+            Err(AuthError::WrongPassword)
+        }
+    }
+}
+
+/// Writes a failed login attempt to the audit log, noting why it failed.
+pub fn log_failed_login(
+    conn: &Connection,
+    user_id: Option<&Uuid>,
+    handle: &str,
+    ip: Option<&str>,
+    reason: &str,
+) {
+    let details = json!({ "handle": handle, "ip": ip, "reason": reason });
+    if let Err(e) = audit::record(
+        conn,
+        None,
+        Action::LoginFailed,
+        user_id,
+        None,
+        Some(details),
+    ) {
+        eprintln!("failed to log failed login: {e}");
+    }
+}
+
+fn authenticate_basic(
+    credentials: &str,
+    ip: Option<&str>,
+    conn: &Connection,
+) -> Result<Option<User>, AuthError> {
     let decoded = BASE64_STANDARD.decode(credentials)?;
     let credentials_str = String::from_utf8(decoded)?;
 
@@ -142,35 +348,67 @@ fn authenticate_basic(credentials: &str, conn: &Connection) -> Result<Option<Use
         return Err(AuthError::InvalidFormat);
     };
 
-    let user_result = conn
-        .prepare("SELECT id, passhash FROM users WHERE handle = ?1")?
-        .query_row([username], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        });
-
-    match user_result {
-        Ok((id_str, passhash)) => {
-            if verify_password(password, &passhash).map_err(|_| AuthError::InvalidCredentials)? {
-                let user_id =
-                    Uuid::from_str(&id_str).map_err(|_| UserStructError::NonUuidPrimaryKey)?;
-                let user = User::get_by_uuid(&user_id, conn)?;
-                Ok(Some(user))
-            } else {
-                Err(AuthError::InvalidCredentials)
-            }
-        }
-        Err(rusqlite::Error::QueryReturnedNoRows) => Err(AuthError::InvalidCredentials),
-        Err(e) => Err(AuthError::DatabaseError(e)),
+    let user_id = check_credentials(username, password, ip, conn)?;
+    // the password alone isn't enough for accounts with a second factor
+    if totp::is_enabled(&user_id, conn)? {
+        return Err(AuthError::SecondFactorRequired);
     }
+    Ok(Some(User::get_by_uuid(&user_id, conn)?))
 }
 
 fn authenticate_bearer(token: &str, conn: &Connection) -> Result<Option<User>, AuthError> {
-    let session = Session::get_by_token(token, conn)?;
+    if token.starts_with(TOKEN_PREFIX) {
+        let api_token = match ApiToken::authenticate(token, conn) {
+            Ok(t) => t,
+            Err(ApiTokenError::NotFound) => return Err(AuthError::InvalidCredentials),
+            Err(e) => return Err(e.into()),
+        };
+        let mut user = User::get_by_uuid(&api_token.user_id, conn)?;
+        user.scopes = Some(api_token.scopes);
+        return Ok(Some(user));
+    }
+
+    let mut session = Session::get_by_token(token, conn)?;
 
     if session.is_expired_or_revoked() {
         return Err(AuthError::InvalidCredentials);
     }
+    session.touch(conn)?;
 
     let user = User::get_by_uuid(session.user_id(), conn)?;
     Ok(Some(user))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::test_db, users::roles::Role};
+
+    fn failures(key: &str, conn: &Connection) -> Option<u32> {
+        conn.query_one(
+            "SELECT failures FROM login_attempts WHERE key = ?1",
+            [key],
+            |r| r.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn wrong_passwords_count_against_the_account_and_the_ip() {
+        let conn = test_db();
+        let user = User::create("ala", "dobrehaslo123", Role::Counter, &conn).unwrap();
+        assert!(matches!(
+            check_credentials("ala", "zlehaslo", Some("10.0.0.1"), &conn),
+            Err(AuthError::WrongPassword)
+        ));
+        assert_eq!(failures("account:ala", &conn), Some(1));
+        assert_eq!(failures("ip:10.0.0.1", &conn), Some(1));
+
+        let id = check_credentials("ala", "dobrehaslo123", Some("10.0.0.1"), &conn).unwrap();
+        assert_eq!(id, user.id);
+        // a network keeps its count, as others on it may be guessing
+        assert_eq!(failures("account:ala", &conn), None);
+        assert_eq!(failures("ip:10.0.0.1", &conn), Some(1));
+    }
+}
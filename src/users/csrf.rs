@@ -0,0 +1,182 @@
+use axum::{
+    extract::{FromRequest, RawForm, Request},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use maud::{Markup, Render, html};
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+    crypto::{constant_time_eq, hash_token},
+    flash::Flash,
+    users::auth::cookie_token,
+};
+
+/// Name of the hidden field carrying the token in every panel form.
+const FIELD: &str = "csrf";
+const MISMATCH_MSG: &str =
+    "Formularz wygasł albo nie pochodzi z tego panelu. Odśwież stronę i spróbuj ponownie.";
+
+/// Anti-forgery token of the session a page was requested in. It's derived from the
+/// session token, so it needs no storage of its own and changes with every login.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Token for the session in the request's auth cookie. Requests without one get an
+    /// empty token, as they can't be forged using the visitor's cookie anyway.
+    pub fn from_headers(headers: &HeaderMap) -> CsrfToken {
+        CsrfToken(
+            cookie_token(headers)
+                .map(|token| hash_token(&format!("{FIELD}:{token}")))
+                .unwrap_or_default(),
+        )
+    }
+    fn matches(&self, sent: &str) -> bool {
+        constant_time_eq(&self.0, sent)
+    }
+}
+
+/// Renders as the hidden form field [`CsrfForm`] checks.
+impl Render for CsrfToken {
+    fn render(&self) -> Markup {
+        html! { input type="hidden" name=(FIELD) value=(self.0); }
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrfField {
+    csrf: Option<String>,
+}
+
+/// Fields of a panel form that only has a button.
+#[derive(Deserialize)]
+pub struct NoFields {}
+
+/// Like [`axum::Form`], but first checks the form's anti-forgery token against the
+/// session in the auth cookie, if the request carries one. Requests authenticated with
+/// an `Authorization` header only aren't checked, as browsers never attach it on their own.
+pub struct CsrfForm<T>(pub T);
+
+impl<S, T> FromRequest<S> for CsrfForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let expected = cookie_token(req.headers())
+            .is_some()
+            .then(|| CsrfToken::from_headers(req.headers()));
+        let RawForm(body) = RawForm::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        if let Some(expected) = expected {
+            let sent = serde_urlencoded::from_bytes::<CsrfField>(&body)
+                .ok()
+                .and_then(|f| f.csrf)
+                .unwrap_or_default();
+            if !expected.matches(&sent) {
+                return Err(Flash::error(MISMATCH_MSG)
+                    .redirect("/panel")
+                    .into_response());
+            }
+        }
+        serde_urlencoded::from_bytes(&body)
+            .map(CsrfForm)
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header};
+
+    use super::*;
+    use crate::{crypto::init_test_token_key, users::auth::COOKIE_NAME};
+
+    #[derive(Deserialize)]
+    struct NoteForm {
+        note: String,
+    }
+
+    fn with_session(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let cookie = format!("{COOKIE_NAME}={token}");
+        headers.insert(header::COOKIE, cookie.parse().unwrap());
+        headers
+    }
+
+    fn post(session: Option<&str>, body: String) -> Request {
+        let mut request = Request::post("/panel")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(token) = session {
+            request = request.header(header::COOKIE, format!("{COOKIE_NAME}={token}"));
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    #[test]
+    fn tokens_are_derived_from_the_session() {
+        init_test_token_key();
+        let a = CsrfToken::from_headers(&with_session("SESJA-A"));
+        assert_eq!(a.0, CsrfToken::from_headers(&with_session("SESJA-A")).0);
+        assert_ne!(a.0, CsrfToken::from_headers(&with_session("SESJA-B")).0);
+        assert!(!a.0.is_empty());
+        assert!(!a.0.contains("SESJA-A"));
+        assert_eq!(CsrfToken::from_headers(&HeaderMap::new()).0, "");
+    }
+
+    #[test]
+    fn tokens_match_only_exactly() {
+        init_test_token_key();
+        let token = CsrfToken::from_headers(&with_session("SESJA"));
+        assert!(token.matches(&token.0.clone()));
+        assert!(!token.matches(""));
+        assert!(!token.matches(&token.0[1..]));
+        assert!(!token.matches(&format!("{}0", token.0)));
+        let mut flipped = token.0.clone();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        assert!(!token.matches(&flipped));
+    }
+
+    #[tokio::test]
+    async fn forms_need_the_sessions_token() {
+        init_test_token_key();
+        let token = CsrfToken::from_headers(&with_session("SESJA")).0;
+
+        let sent = post(Some("SESJA"), format!("{FIELD}={token}&note=hej"));
+        let CsrfForm(form) = CsrfForm::<NoteForm>::from_request(sent, &())
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(form.note, "hej");
+
+        for body in [
+            "note=hej".to_owned(),
+            format!("{FIELD}=&note=hej"),
+            format!("{FIELD}={token}x&note=hej"),
+        ] {
+            let rejected = CsrfForm::<NoteForm>::from_request(post(Some("SESJA"), body), &())
+                .await
+                .err()
+                .unwrap();
+            assert!(rejected.status().is_redirection());
+        }
+        // a token from another session doesn't do either
+        let sent = post(Some("INNA-SESJA"), format!("{FIELD}={token}&note=hej"));
+        assert!(CsrfForm::<NoteForm>::from_request(sent, &()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn forms_without_a_session_cookie_are_not_checked() {
+        let sent = post(None, "note=hej".to_owned());
+        let CsrfForm(form) = CsrfForm::<NoteForm>::from_request(sent, &())
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(form.note, "hej");
+    }
+}
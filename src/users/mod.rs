@@ -1,17 +1,40 @@
 use std::str::FromStr;
 
-use rusqlite::Connection;
+use chrono::Utc;
+use rusqlite::{Connection, ErrorCode};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 pub mod auth;
+pub mod csrf;
 pub mod pwd;
+pub mod roles;
 pub mod sessions;
+pub mod throttle;
+pub mod tokens;
+pub mod totp;
+
+use crate::users::{
+    auth::AuthError,
+    pwd::{hash_password, verify_password},
+    roles::{Permission, Role},
+    tokens::Scope,
+};
+
+pub const MIN_PASSWORD_LEN: usize = 8;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub handle: String,
+    pub role: Role,
+    /// Set when the request was authenticated with an API token, limiting it to these scopes.
+    #[serde(skip)]
+    pub scopes: Option<Vec<Scope>>,
+    /// Set when the user's role requires two-factor authentication they haven't
+    /// enabled yet; such a user can't do anything but set it up.
+    #[serde(skip)]
+    pub needs_totp: bool,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -20,22 +43,210 @@ pub enum UserStructError {
     UserSqlError(#[from] rusqlite::Error),
     #[error("Non-UUID User PK found in DB")]
     NonUuidPrimaryKey,
+    #[error("Unknown user role found in DB")]
+    UnknownRole,
+    #[error("Failed to hash password: {0}")]
+    PasswordHashError(#[from] argon2::password_hash::Error),
+    #[error("User handle must not be empty")]
+    EmptyHandle,
+    #[error("User handle is already taken")]
+    HandleTaken,
+    #[error("Password is too short")]
+    PasswordTooShort,
+    #[error("User does not exist")]
+    NotFound,
+    #[error("The infradmin account cannot be removed")]
+    InfradminUndeletable,
+    #[error("The infradmin account's role cannot be changed")]
+    InfradminRoleFixed,
+}
+impl UserStructError {
+    pub fn msg(&self) -> &'static str {
+        use UserStructError as USE;
+        match self {
+            USE::UserSqlError(_)
+            | USE::NonUuidPrimaryKey
+            | USE::UnknownRole
+            | USE::PasswordHashError(_) => "Błąd serwera. Skontaktuj się z webmasterem.",
+            USE::EmptyHandle => "Login nie może być pusty.",
+            USE::HandleTaken => "Konto o tym loginie już istnieje.",
+            USE::PasswordTooShort => "Hasło musi mieć co najmniej 8 znaków.",
+            USE::NotFound => "Takie konto nie istnieje.",
+            USE::InfradminUndeletable => "Nie można usunąć konta administratora infrastruktury.",
+            USE::InfradminRoleFixed => {
+                "Nie można zmienić roli konta administratora infrastruktury."
+            }
+        }
+    }
+}
+
+/// Builds a `User` out of raw `(id, handle, role)` columns.
+fn user_from_row((id, handle, role): (String, String, String)) -> Result<User, UserStructError> {
+    Ok(User {
+        id: Uuid::from_str(&id).map_err(|_| UserStructError::NonUuidPrimaryKey)?,
+        handle,
+        role: Role::from_str(&role).map_err(|_| UserStructError::UnknownRole)?,
+        scopes: None,
+        needs_totp: false,
+    })
+}
+
+fn check_password_len(password: &str) -> Result<(), UserStructError> {
+    match password.chars().count() < MIN_PASSWORD_LEN {
+        true => Err(UserStructError::PasswordTooShort),
+        false => Ok(()),
+    }
 }
 
 impl User {
-    pub fn is_infradmin(&self) -> bool {
-        self.id.is_max()
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.can(permission)
+            && !self.needs_totp
+            && self
+                .scopes
+                .as_ref()
+                .is_none_or(|scopes| scopes.iter().any(|s| s.allows(permission)))
+    }
+    /// Like [`User::can`], but as an error that handlers can bubble up with `?`.
+    pub fn require(&self, permission: Permission) -> Result<(), AuthError> {
+        match self.can(permission) {
+            true => Ok(()),
+            false => Err(AuthError::Forbidden),
+        }
+    }
+    /// Checks an API token's scope. Requests authenticated with a session or
+    /// password aren't limited by scopes.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AuthError> {
+        match self
+            .scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+        {
+            true => Ok(()),
+            false => Err(AuthError::Forbidden),
+        }
+    }
+    /// Account security settings can only be changed from a panel session or with the
+    /// password, never with an API token.
+    pub fn require_session(&self) -> Result<(), AuthError> {
+        match self.scopes {
+            None => Ok(()),
+            Some(_) => Err(AuthError::Forbidden),
+        }
     }
     pub fn get_by_uuid(uuid: &Uuid, conn: &Connection) -> Result<User, UserStructError> {
         let pk = uuid.to_string();
-        let (id, handle) = conn
-            .prepare("SELECT * FROM users WHERE id = ?1")?
-            .query_row([&pk], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        let row = conn
+            .prepare("SELECT id, handle, role FROM users WHERE id = ?1")?
+            .query_row([&pk], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        user_from_row(row)
+    }
+    pub fn get_all(conn: &Connection) -> Result<Vec<User>, UserStructError> {
+        conn.prepare("SELECT id, handle, role FROM users ORDER BY handle")?
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(user_from_row)
+            .collect()
+    }
+    pub fn create(
+        handle: &str,
+        password: &str,
+        role: Role,
+        conn: &Connection,
+    ) -> Result<User, UserStructError> {
+        let handle = handle.trim();
+        if handle.is_empty() {
+            return Err(UserStructError::EmptyHandle);
+        }
+        check_password_len(password)?;
+        let user = User {
+            id: Uuid::now_v7(),
+            handle: handle.to_owned(),
+            role,
+            scopes: None,
+            needs_totp: false,
+        };
+        conn.prepare("INSERT INTO users (id, handle, passhash, role) VALUES (?1, ?2, ?3, ?4)")?
+            .execute([
+                user.id.to_string(),
+                user.handle.clone(),
+                hash_password(password)?,
+                role.as_str().to_owned(),
+            ])
+            .map_err(|e| match e.sqlite_error_code() {
+                Some(ErrorCode::ConstraintViolation) => UserStructError::HandleTaken,
+                _ => UserStructError::UserSqlError(e),
             })?;
-        Ok(User {
-            id: Uuid::from_str(&id).map_err(|_| UserStructError::NonUuidPrimaryKey)?,
-            handle,
-        })
+        Ok(user)
+    }
+    pub fn check_password(
+        &self,
+        candidate: &str,
+        conn: &Connection,
+    ) -> Result<bool, UserStructError> {
+        let passhash = conn
+            .prepare("SELECT passhash FROM users WHERE id = ?1")?
+            .query_row([self.id.to_string()], |r| r.get::<_, String>(0))?;
+        Ok(verify_password(candidate, &passhash)?)
+    }
+    pub fn set_password(
+        id: &Uuid,
+        password: &str,
+        conn: &Connection,
+    ) -> Result<(), UserStructError> {
+        check_password_len(password)?;
+        let changed = conn
+            .prepare("UPDATE users SET passhash = ?2 WHERE id = ?1")?
+            .execute([id.to_string(), hash_password(password)?])?;
+        match changed {
+            0 => Err(UserStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    /// Changes a user's role. The built-in infradmin account always stays infradmin,
+    /// so the instance can never be left without someone able to manage accounts.
+    pub fn set_role(id: &Uuid, role: Role, conn: &Connection) -> Result<(), UserStructError> {
+        if id.is_max() {
+            return Err(UserStructError::InfradminRoleFixed);
+        }
+        match conn
+            .prepare("UPDATE users SET role = ?2 WHERE id = ?1")?
+            .execute([id.to_string(), role.as_str().to_owned()])?
+        {
+            0 => Err(UserStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    /// Revokes every session the user has open, e.g. after an admin password reset.
+    pub fn revoke_sessions(id: &Uuid, conn: &Connection) -> Result<(), UserStructError> {
+        conn.prepare(
+            "UPDATE sessions SET revoked = 1, revoked_at = ?2 WHERE user_id = ?1 AND revoked = 0",
+        )?
+        .execute([id.to_string(), Utc::now().timestamp().to_string()])?;
+        Ok(())
+    }
+    /// Removes a user along with their sessions and credentials. Contributions they
+    /// recorded are kept.
+    pub fn delete(id: &Uuid, conn: &Connection) -> Result<(), UserStructError> {
+        if id.is_max() {
+            return Err(UserStructError::InfradminUndeletable);
+        }
+        let pk = id.to_string();
+        conn.prepare("DELETE FROM sessions WHERE user_id = ?1")?
+            .execute([&pk])?;
+        conn.prepare("DELETE FROM api_tokens WHERE user_id = ?1")?
+            .execute([&pk])?;
+        conn.prepare("DELETE FROM recovery_codes WHERE user_id = ?1")?
+            .execute([&pk])?;
+        conn.prepare("DELETE FROM login_challenges WHERE user_id = ?1")?
+            .execute([&pk])?;
+        match conn
+            .prepare("DELETE FROM users WHERE id = ?1")?
+            .execute([&pk])?
+        {
+            0 => Err(UserStructError::NotFound),
+            _ => Ok(()),
+        }
     }
 }
@@ -0,0 +1,125 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Runs the instance; the only role allowed to manage accounts.
+    Infradmin,
+    /// Runs the competition: containers and everything around the donations.
+    Organizer,
+    /// Sits at the collection desk and records donations.
+    Counter,
+    /// Can look around the panel without changing anything.
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    RecordContributions,
+    /// Browse recorded contributions and their notes.
+    ViewContributions,
+    /// Correct and void contributions recorded by others; anyone can amend their own.
+    ManageContributions,
+    ManageContainers,
+    ManageUsers,
+    ViewLogs,
+    ManageJobs,
+    ManageConfig,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [
+        Role::Infradmin,
+        Role::Organizer,
+        Role::Counter,
+        Role::Viewer,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Infradmin => "infradmin",
+            Role::Organizer => "organizer",
+            Role::Counter => "counter",
+            Role::Viewer => "viewer",
+        }
+    }
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Role::Infradmin => "administrator infrastruktury",
+            Role::Organizer => "organizator",
+            Role::Counter => "liczący datki",
+            Role::Viewer => "obserwator",
+        }
+    }
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission as P;
+        match self {
+            Role::Infradmin => true,
+            Role::Organizer => matches!(
+                permission,
+                P::RecordContributions
+                    | P::ViewContributions
+                    | P::ManageContributions
+                    | P::ManageContainers
+                    | P::ViewLogs
+                    | P::ManageConfig
+            ),
+            Role::Counter => matches!(permission, P::RecordContributions | P::ViewContributions),
+            Role::Viewer => matches!(permission, P::ViewContributions | P::ViewLogs),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL.into_iter().find(|r| r.as_str() == s).ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_have_exactly_their_permissions() {
+        use Permission as P;
+        // columns: infradmin, organizer, counter, viewer; a new permission won't
+        // compile until it's added here
+        let allowed = |permission| match permission {
+            P::RecordContributions => [true, true, true, false],
+            P::ViewContributions => [true, true, true, true],
+            P::ManageContributions => [true, true, false, false],
+            P::ManageContainers => [true, true, false, false],
+            P::ManageUsers => [true, false, false, false],
+            P::ViewLogs => [true, true, false, true],
+            P::ManageJobs => [true, false, false, false],
+            P::ManageConfig => [true, true, false, false],
+        };
+        let permissions = [
+            P::RecordContributions,
+            P::ViewContributions,
+            P::ManageContributions,
+            P::ManageContainers,
+            P::ManageUsers,
+            P::ViewLogs,
+            P::ManageJobs,
+            P::ManageConfig,
+        ];
+        for permission in permissions {
+            for (role, allowed) in Role::ALL.into_iter().zip(allowed(permission)) {
+                assert_eq!(role.can(permission), allowed, "{role:?} {permission:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+        assert!("admin".parse::<Role>().is_err());
+    }
+}
@@ -1,10 +1,58 @@
-use std::str::FromStr;
+use std::{error::Error, str::FromStr, sync::OnceLock};
 
 use chrono::{DateTime, Duration, Utc};
-use rusqlite::Connection;
+use rusqlite::{Connection, Row};
 use uuid::Uuid;
 
-use crate::crypto::generate_short_token;
+use crate::crypto::{generate_long_token, hash_token};
+
+const DEFAULT_IDLE_DAYS: i64 = 7;
+const DEFAULT_LIFETIME_DAYS: i64 = 30;
+/// `last_access` is written at most this often per session, so that browsing the
+/// panel doesn't turn every page view into a database write.
+const TOUCH_INTERVAL: Duration = Duration::minutes(1);
+
+/// How long sessions last: `idle` since the last request, but never more than
+/// `lifetime` since logging in.
+#[derive(Clone, Copy)]
+pub struct SessionPolicy {
+    idle: Duration,
+    lifetime: Duration,
+}
+static POLICY: OnceLock<SessionPolicy> = OnceLock::new();
+
+impl SessionPolicy {
+    /// Reads the policy from `SESSION_IDLE_DAYS` and `SESSION_LIFETIME_DAYS`.
+    /// Call once at startup; sessions use the defaults until then.
+    pub fn init_from_env() -> Result<(), Box<dyn Error>> {
+        let days = |var: &str, default: i64| -> Result<i64, Box<dyn Error>> {
+            match std::env::var(var) {
+                Ok(d) => match d.parse::<i64>()? {
+                    d if d > 0 => Ok(d),
+                    _ => Err(format!("{var} must be a positive number of days").into()),
+                },
+                Err(std::env::VarError::NotPresent) => Ok(default),
+                Err(e) => Err(e)?,
+            }
+        };
+        let policy = SessionPolicy {
+            idle: Duration::days(days("SESSION_IDLE_DAYS", DEFAULT_IDLE_DAYS)?),
+            lifetime: Duration::days(days("SESSION_LIFETIME_DAYS", DEFAULT_LIFETIME_DAYS)?),
+        };
+        _ = POLICY.set(policy);
+        Ok(())
+    }
+    fn get() -> SessionPolicy {
+        *POLICY.get_or_init(|| SessionPolicy {
+            idle: Duration::days(DEFAULT_IDLE_DAYS),
+            lifetime: Duration::days(DEFAULT_LIFETIME_DAYS),
+        })
+    }
+    /// Expiry of a session issued at `issued` and last used at `now`.
+    fn expiry(&self, issued: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        (now + self.idle).min(issued + self.lifetime)
+    }
+}
 
 pub struct Session {
     id: Uuid,
@@ -12,7 +60,8 @@ pub struct Session {
     expiry: DateTime<Utc>,
     last_access: DateTime<Utc>,
     revoked: bool,
-    revoked_at: Option<DateTime<Utc>>,
+    user_agent: Option<String>,
+    ip: Option<String>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -23,89 +72,289 @@ pub enum SessionStructError {
     NonUuidPrimaryKey,
     #[error("Non-UUID UserId found in DB")]
     NonUuidUserId,
+    #[error("Session does not exist or has already ended")]
+    NotFound,
+}
+impl SessionStructError {
+    pub fn msg(&self) -> &'static str {
+        use SessionStructError as SSE;
+        match self {
+            SSE::UserSqlError(_) | SSE::NonUuidPrimaryKey | SSE::NonUuidUserId => {
+                "Błąd serwera. Skontaktuj się z webmasterem."
+            }
+            SSE::NotFound => "Ta sesja nie istnieje lub już wygasła.",
+        }
+    }
+}
+
+const SESSION_COLUMNS: &str = "id, user_id, expiry, last_access, revoked, user_agent, ip";
+
+type SessionRow = (
+    String,
+    String,
+    i64,
+    i64,
+    bool,
+    Option<String>,
+    Option<String>,
+);
+
+fn read_row(row: &Row) -> Result<SessionRow, rusqlite::Error> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+    ))
+}
+
+/// Builds a `Session` out of a row selected with [`SESSION_COLUMNS`].
+fn session_from_row(
+    (id, user_id, expiry, last_access, revoked, user_agent, ip): SessionRow,
+) -> Result<Session, SessionStructError> {
+    Ok(Session {
+        id: Uuid::from_str(&id).map_err(|_| SessionStructError::NonUuidPrimaryKey)?,
+        user_id: Uuid::from_str(&user_id).map_err(|_| SessionStructError::NonUuidUserId)?,
+        expiry: DateTime::from_timestamp(expiry, 0).unwrap(),
+        last_access: DateTime::from_timestamp(last_access, 0).unwrap(),
+        revoked,
+        user_agent,
+        ip,
+    })
 }
 
 impl Session {
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
     pub fn user_id(&self) -> &Uuid {
         &self.user_id
     }
+    pub fn expiry(&self) -> DateTime<Utc> {
+        self.expiry
+    }
+    pub fn last_access(&self) -> DateTime<Utc> {
+        self.last_access
+    }
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+    pub fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
 
     pub fn is_expired_or_revoked(&self) -> bool {
         self.expiry <= Utc::now() || self.revoked
     }
 
-    /// Create a new session for a user. Returns the session token that should be stored in the cookie.
-    pub fn create(user_id: &Uuid, conn: &Connection) -> Result<String, SessionStructError> {
+    /// Create a new session for a user. Returns the session token that should be stored
+    /// in the cookie, along with the session's expiry.
+    pub fn create(
+        user_id: &Uuid,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        conn: &Connection,
+    ) -> Result<(String, DateTime<Utc>), SessionStructError> {
         let session_id = Uuid::now_v7();
-        let token = generate_short_token();
+        let token = generate_long_token();
         let now = Utc::now();
-        let expiry = now + Duration::days(30); // 30 day expiry
+        let expiry = SessionPolicy::get().expiry(now, now);
 
         conn.prepare(
-            "INSERT INTO sessions (id, token, user_id, expiry, last_access, revoked)
-             VALUES (?1, ?2, ?3, ?4, ?5, 0)",
+            "INSERT INTO sessions (id, token_hash, user_id, expiry, last_access, revoked, user_agent, ip)
+             VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?7)",
         )?
-        .execute([
+        .execute((
             session_id.to_string(),
-            token.clone(),
+            hash_token(&token),
             user_id.to_string(),
-            expiry.timestamp().to_string(),
-            now.timestamp().to_string(),
-        ])?;
+            expiry.timestamp(),
+            now.timestamp(),
+            user_agent,
+            ip,
+        ))?;
 
-        Ok(token)
+        Ok((token, expiry))
+    }
+    /// Records that the session was just used, pushing its expiry forward by the idle
+    /// timeout (capped at the absolute lifetime). Throttled to [`TOUCH_INTERVAL`].
+    pub fn touch(&mut self, conn: &Connection) -> Result<(), SessionStructError> {
+        let now = Utc::now();
+        if now - self.last_access < TOUCH_INTERVAL {
+            return Ok(());
+        }
+        let expiry = SessionPolicy::get().expiry(self.issued(), now);
+        conn.prepare("UPDATE sessions SET last_access = ?2, expiry = ?3 WHERE id = ?1")?
+            .execute((self.id.to_string(), now.timestamp(), expiry.timestamp()))?;
+        self.last_access = now;
+        self.expiry = expiry;
+        Ok(())
     }
     pub fn issued(&self) -> DateTime<Utc> {
-        let timestamp = self.id.get_timestamp().unwrap();
-        DateTime::from_timestamp_millis(timestamp.to_unix().0 as i64).unwrap()
+        let (secs, nanos) = self.id.get_timestamp().unwrap().to_unix();
+        DateTime::from_timestamp(secs as i64, nanos).unwrap()
     }
     pub fn get_by_id(id: &Uuid, conn: &Connection) -> Result<Session, SessionStructError> {
-        let pk = id.to_string();
-        const QUERY: &str = "
-            SELECT id, user_id, expiry, last_access, revoked, revoked_at
-            FROM sessions WHERE id = ?1
-        ";
-        let res = conn.prepare(QUERY)?.query_one([&pk], |row| {
-            Ok((
-                row.get::<_, String>(0)?,      // id
-                row.get::<_, String>(1)?,      // user_id
-                row.get::<_, i64>(2)?,         // expiry
-                row.get::<_, i64>(3)?,         // last_access
-                row.get::<_, bool>(4)?,        // revoked
-                row.get::<_, Option<i64>>(5)?, // revoked_at
-            ))
-        })?;
-        Ok(Session {
-            id: Uuid::from_str(&res.0).map_err(|_| SessionStructError::NonUuidPrimaryKey)?,
-            user_id: Uuid::from_str(&res.1).map_err(|_| SessionStructError::NonUuidUserId)?,
-            expiry: DateTime::from_timestamp(res.2, 0).unwrap(),
-            last_access: DateTime::from_timestamp(res.3, 0).unwrap(),
-            revoked: res.4,
-            revoked_at: res.5.map(|ts| DateTime::from_timestamp(ts, 0).unwrap()),
-        })
+        conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions WHERE id = ?1"
+        ))?
+        .query_one([id.to_string()], read_row)
+        .map_err(SessionStructError::from)
+        .and_then(session_from_row)
     }
     pub fn get_by_token(token: &str, conn: &Connection) -> Result<Session, SessionStructError> {
-        const QUERY: &str = "
-            SELECT id, user_id, expiry, last_access, revoked, revoked_at
-            FROM sessions WHERE token = ?1
-        ";
-        let res = conn.prepare(QUERY)?.query_one([token], |row| {
-            Ok((
-                row.get::<_, String>(0)?,      // id
-                row.get::<_, String>(1)?,      // user_id
-                row.get::<_, i64>(2)?,         // expiry
-                row.get::<_, i64>(3)?,         // last_access
-                row.get::<_, bool>(4)?,        // revoked
-                row.get::<_, Option<i64>>(5)?, // revoked_at
-            ))
-        })?;
-        Ok(Session {
-            id: Uuid::from_str(&res.0).map_err(|_| SessionStructError::NonUuidPrimaryKey)?,
-            user_id: Uuid::from_str(&res.1).map_err(|_| SessionStructError::NonUuidUserId)?,
-            expiry: DateTime::from_timestamp(res.2, 0).unwrap(),
-            last_access: DateTime::from_timestamp(res.3, 0).unwrap(),
-            revoked: res.4,
-            revoked_at: res.5.map(|ts| DateTime::from_timestamp(ts, 0).unwrap()),
-        })
+        conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions WHERE token_hash = ?1"
+        ))?
+        .query_one([hash_token(token)], read_row)
+        .map_err(SessionStructError::from)
+        .and_then(session_from_row)
+    }
+    /// Sessions that are neither expired nor revoked, newest first.
+    /// Limited to one user's sessions if `user_id` is given.
+    pub fn get_active(
+        user_id: Option<&Uuid>,
+        conn: &Connection,
+    ) -> Result<Vec<Session>, SessionStructError> {
+        conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions
+             WHERE revoked = 0 AND expiry > ?1 AND (?2 IS NULL OR user_id = ?2)
+             ORDER BY id DESC"
+        ))?
+        .query_map(
+            (Utc::now().timestamp(), user_id.map(|u| u.to_string())),
+            read_row,
+        )?
+        .map(|r| session_from_row(r?))
+        .collect()
+    }
+    /// Ends a session right away, no matter how long it had left.
+    pub fn revoke(id: &Uuid, conn: &Connection) -> Result<(), SessionStructError> {
+        match conn
+            .prepare(
+                "UPDATE sessions SET revoked = 1, revoked_at = ?2 WHERE id = ?1 AND revoked = 0",
+            )?
+            .execute((id.to_string(), Utc::now().timestamp()))?
+        {
+            0 => Err(SessionStructError::NotFound),
+            _ => Ok(()),
+        }
+    }
+    /// Revokes every session of a user except `keep`. Returns how many were revoked.
+    pub fn revoke_others(
+        user_id: &Uuid,
+        keep: &Uuid,
+        conn: &Connection,
+    ) -> Result<usize, SessionStructError> {
+        Ok(conn
+            .prepare(
+                "UPDATE sessions SET revoked = 1, revoked_at = ?3
+                 WHERE user_id = ?1 AND id != ?2 AND revoked = 0",
+            )?
+            .execute((
+                user_id.to_string(),
+                keep.to_string(),
+                Utc::now().timestamp(),
+            ))?)
+    }
+    /// Deletes sessions that ended more than a day ago, whether by expiry or
+    /// revocation. Returns how many were removed.
+    pub fn delete_stale(conn: &Connection) -> Result<usize, SessionStructError> {
+        let cutoff = (Utc::now() - Duration::days(1)).timestamp();
+        Ok(conn
+            .prepare("DELETE FROM sessions WHERE expiry < ?1 OR (revoked = 1 AND revoked_at < ?1)")?
+            .execute([cutoff])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::init_test_token_key, database::test_db};
+
+    const POLICY: SessionPolicy = SessionPolicy {
+        idle: Duration::days(DEFAULT_IDLE_DAYS),
+        lifetime: Duration::days(DEFAULT_LIFETIME_DAYS),
+    };
+
+    /// A session issued at `issued` and last used a while ago, returning its token.
+    fn session(conn: &Connection, issued: DateTime<Utc>) -> String {
+        let user = Uuid::now_v7().to_string();
+        conn.execute(
+            "INSERT INTO users (id, handle, passhash, role) VALUES (?1, ?1, '', 'viewer')",
+            [&user],
+        )
+        .unwrap();
+        let id = Uuid::new_v7(uuid::Timestamp::from_unix(
+            uuid::NoContext,
+            issued.timestamp() as u64,
+            0,
+        ));
+        let token = generate_long_token();
+        let last_access = Utc::now() - TOUCH_INTERVAL * 2;
+        conn.execute(
+            "INSERT INTO sessions (id, token_hash, user_id, expiry, last_access, revoked)
+             VALUES (?1, ?2, ?3, ?4, ?5, 0)",
+            (
+                id.to_string(),
+                hash_token(&token),
+                user,
+                (last_access + POLICY.idle).timestamp(),
+                last_access.timestamp(),
+            ),
+        )
+        .unwrap();
+        token
+    }
+
+    #[test]
+    fn expiry_slides_up_to_the_lifetime() {
+        let now = Utc::now();
+        let SessionPolicy { idle, lifetime } = POLICY;
+        assert_eq!(POLICY.expiry(now, now), now + idle);
+        assert_eq!(POLICY.expiry(now - Duration::days(10), now), now + idle);
+        let issued = now - lifetime + Duration::days(2);
+        assert_eq!(POLICY.expiry(issued, now), issued + lifetime);
+        assert!(POLICY.expiry(now - lifetime - Duration::days(1), now) < now);
+    }
+
+    #[test]
+    fn touching_pushes_expiry_forward_but_not_past_the_lifetime() {
+        init_test_token_key();
+        let conn = test_db();
+        let issued = Utc::now() - Duration::hours(1);
+        let token = session(&conn, issued);
+        let mut s = Session::get_by_token(&token, &conn).unwrap();
+        let before = s.expiry();
+        s.touch(&conn).unwrap();
+        let after = Session::get_by_token(&token, &conn).unwrap().expiry();
+        assert!(after > before);
+        assert!((after - (Utc::now() + POLICY.idle)).num_seconds().abs() <= 1);
+
+        let issued = Utc::now() - POLICY.lifetime + Duration::days(2);
+        let token = session(&conn, issued);
+        let mut s = Session::get_by_token(&token, &conn).unwrap();
+        s.touch(&conn).unwrap();
+        let after = Session::get_by_token(&token, &conn).unwrap().expiry();
+        assert_eq!(after.timestamp(), (issued + POLICY.lifetime).timestamp());
+    }
+
+    #[test]
+    fn touching_again_right_away_writes_nothing() {
+        init_test_token_key();
+        let conn = test_db();
+        let token = session(&conn, Utc::now() - Duration::hours(1));
+        let mut s = Session::get_by_token(&token, &conn).unwrap();
+        s.touch(&conn).unwrap();
+        conn.execute("UPDATE sessions SET expiry = 0", []).unwrap();
+        s.touch(&conn).unwrap();
+        assert!(
+            Session::get_by_token(&token, &conn)
+                .unwrap()
+                .is_expired_or_revoked()
+        );
     }
 }
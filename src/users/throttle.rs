@@ -0,0 +1,205 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, OptionalExtension};
+
+/// Failed logins allowed for one account; any further one locks it out.
+const ACCOUNT_FREE_ATTEMPTS: u32 = 5;
+/// Failed logins allowed from one IP address, which may well be shared by a whole network.
+const IP_FREE_ATTEMPTS: u32 = 20;
+/// Lockout after the first failure over the limit, doubling with every further one.
+const BASE_LOCKOUT: Duration = Duration::seconds(30);
+const MAX_LOCKOUT: Duration = Duration::hours(1);
+/// A counter with no failures for this long starts over.
+const FORGET_AFTER: Duration = Duration::hours(24);
+
+/// Something failed logins are counted against.
+pub enum Subject<'a> {
+    /// A handle, whether or not an account with it exists.
+    Account(&'a str),
+    Ip(&'a str),
+}
+
+impl Subject<'_> {
+    fn key(&self) -> String {
+        match self {
+            Subject::Account(handle) => format!("account:{handle}"),
+            Subject::Ip(ip) => format!("ip:{ip}"),
+        }
+    }
+    fn free_attempts(&self) -> u32 {
+        match self {
+            Subject::Account(_) => ACCOUNT_FREE_ATTEMPTS,
+            Subject::Ip(_) => IP_FREE_ATTEMPTS,
+        }
+    }
+}
+
+/// Until when logging in is blocked for any of the subjects, if it is.
+pub fn locked_until(
+    subjects: &[Subject],
+    conn: &Connection,
+) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+    let now = Utc::now();
+    let mut latest = None;
+    for subject in subjects {
+        let until = conn
+            .prepare("SELECT locked_until FROM login_attempts WHERE key = ?1")?
+            .query_one([subject.key()], |r| r.get::<_, Option<i64>>(0))
+            .optional()?
+            .flatten()
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .filter(|until| *until > now);
+        latest = latest.max(until);
+    }
+    Ok(latest)
+}
+
+/// Counts a failed login against every subject, locking out those over their limit
+/// for exponentially longer.
+pub fn record_failure(subjects: &[Subject], conn: &Connection) -> Result<(), rusqlite::Error> {
+    let now = Utc::now();
+    for subject in subjects {
+        let key = subject.key();
+        let previous = conn
+            .prepare("SELECT failures, last_failure FROM login_attempts WHERE key = ?1")?
+            .query_one([&key], |r| Ok((r.get::<_, u32>(0)?, r.get::<_, i64>(1)?)))
+            .optional()?
+            .filter(|(_, last)| *last >= (now - FORGET_AFTER).timestamp())
+            .map_or(0, |(failures, _)| failures);
+        let failures = previous + 1;
+        // the first failure over the limit locks out for BASE_LOCKOUT
+        let locked_until = failures
+            .checked_sub(subject.free_attempts() + 1)
+            .map(|over| {
+                let lockout = 2_i32
+                    .checked_pow(over)
+                    .map_or(MAX_LOCKOUT, |factor| BASE_LOCKOUT * factor)
+                    .min(MAX_LOCKOUT);
+                (now + lockout).timestamp()
+            });
+        conn.prepare(
+            "INSERT INTO login_attempts (key, failures, last_failure, locked_until)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (key) DO UPDATE SET failures = excluded.failures,
+                last_failure = excluded.last_failure, locked_until = excluded.locked_until",
+        )?
+        .execute((&key, failures, now.timestamp(), locked_until))?;
+    }
+    Ok(())
+}
+
+/// Forgets the failures counted against a subject, after it logged in successfully.
+pub fn clear(subject: &Subject, conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare("DELETE FROM login_attempts WHERE key = ?1")?
+        .execute([subject.key()])?;
+    Ok(())
+}
+
+/// Removes counters that no longer lock anything out and would start over anyway.
+pub fn delete_stale(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let now = Utc::now();
+    conn.prepare(
+        "DELETE FROM login_attempts
+         WHERE last_failure < ?1 AND (locked_until IS NULL OR locked_until < ?2)",
+    )?
+    .execute([(now - FORGET_AFTER).timestamp(), now.timestamp()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+
+    /// How long the subject's last failure locked it out for, in seconds.
+    fn lockout(subject: Subject, conn: &Connection) -> Option<i64> {
+        conn.query_one(
+            "SELECT locked_until - last_failure FROM login_attempts WHERE key = ?1",
+            [subject.key()],
+            |r| r.get::<_, Option<i64>>(0),
+        )
+        .optional()
+        .unwrap()
+        .flatten()
+    }
+
+    fn fail(times: u32, subjects: &[Subject], conn: &Connection) {
+        for _ in 0..times {
+            record_failure(subjects, conn).unwrap();
+        }
+    }
+
+    #[test]
+    fn accounts_lock_only_after_their_free_attempts() {
+        let conn = test_db();
+        let subjects = [Subject::Account("ala")];
+        fail(ACCOUNT_FREE_ATTEMPTS, &subjects, &conn);
+        assert_eq!(lockout(Subject::Account("ala"), &conn), None);
+        fail(1, &subjects, &conn);
+        assert_eq!(
+            lockout(Subject::Account("ala"), &conn),
+            Some(BASE_LOCKOUT.num_seconds())
+        );
+        assert_eq!(lockout(Subject::Account("ola"), &conn), None);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let conn = test_db();
+        let subjects = [Subject::Account("ala")];
+        fail(ACCOUNT_FREE_ATTEMPTS + 1, &subjects, &conn);
+        for doubled in 1..=3 {
+            fail(1, &subjects, &conn);
+            assert_eq!(
+                lockout(Subject::Account("ala"), &conn),
+                Some(BASE_LOCKOUT.num_seconds() << doubled)
+            );
+        }
+        fail(40, &subjects, &conn);
+        assert_eq!(
+            lockout(Subject::Account("ala"), &conn),
+            Some(MAX_LOCKOUT.num_seconds())
+        );
+    }
+
+    #[test]
+    fn failures_count_against_the_ip_across_accounts() {
+        let conn = test_db();
+        let handles = (0..=IP_FREE_ATTEMPTS)
+            .map(|i| format!("konto{i}"))
+            .collect::<Vec<_>>();
+        for handle in &handles[..IP_FREE_ATTEMPTS as usize] {
+            fail(
+                1,
+                &[Subject::Account(handle), Subject::Ip("10.0.0.1")],
+                &conn,
+            );
+        }
+        assert_eq!(lockout(Subject::Ip("10.0.0.1"), &conn), None);
+        let last = &handles[IP_FREE_ATTEMPTS as usize];
+        fail(1, &[Subject::Account(last), Subject::Ip("10.0.0.1")], &conn);
+        assert!(lockout(Subject::Ip("10.0.0.1"), &conn).is_some());
+        assert_eq!(lockout(Subject::Ip("10.0.0.2"), &conn), None);
+        // the accounts themselves each failed only once
+        assert_eq!(lockout(Subject::Account(last), &conn), None);
+        assert!(
+            locked_until(&[Subject::Account(last), Subject::Ip("10.0.0.1")], &conn)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn counters_are_cleared_and_forgotten() {
+        let conn = test_db();
+        let subjects = [Subject::Account("ala")];
+        fail(ACCOUNT_FREE_ATTEMPTS, &subjects, &conn);
+        clear(&Subject::Account("ala"), &conn).unwrap();
+        fail(ACCOUNT_FREE_ATTEMPTS, &subjects, &conn);
+        assert_eq!(lockout(Subject::Account("ala"), &conn), None);
+
+        let long_ago = (Utc::now() - FORGET_AFTER - Duration::minutes(1)).timestamp();
+        conn.execute("UPDATE login_attempts SET last_failure = ?1", [long_ago])
+            .unwrap();
+        fail(1, &subjects, &conn);
+        assert_eq!(lockout(Subject::Account("ala"), &conn), None);
+    }
+}
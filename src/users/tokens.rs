@@ -0,0 +1,219 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    crypto::{generate_long_token, hash_token},
+    users::roles::Permission,
+};
+
+/// Prefix telling API tokens apart from session tokens in `Authorization: Bearer`.
+pub const TOKEN_PREFIX: &str = "wpik_";
+/// `last_used` is written at most this often per token.
+const TOUCH_INTERVAL: Duration = Duration::minutes(1);
+
+/// What an API token may be used for, on top of what its owner's role allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Scope {
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "contributions:write")]
+    ContributionsWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 2] = [Scope::StatsRead, Scope::ContributionsWrite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::StatsRead => "stats:read",
+            Scope::ContributionsWrite => "contributions:write",
+        }
+    }
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Scope::StatsRead => "odczyt statystyk",
+            Scope::ContributionsWrite => "odnotowywanie datków",
+        }
+    }
+    /// Panel permissions a request authenticated with this scope keeps.
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Scope::StatsRead => false,
+            Scope::ContributionsWrite => permission == Permission::RecordContributions,
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL.into_iter().find(|sc| sc.as_str() == s).ok_or(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiTokenError {
+    #[error("Failed to execute SQL: {0}")]
+    TokenSqlError(#[from] rusqlite::Error),
+    #[error("Malformed API token found in DB")]
+    MalformedToken,
+    #[error("API token name must not be empty")]
+    EmptyName,
+    #[error("API token must have at least one scope")]
+    NoScopes,
+    #[error("API token does not exist")]
+    NotFound,
+}
+impl ApiTokenError {
+    pub fn msg(&self) -> &'static str {
+        use ApiTokenError as ATE;
+        match self {
+            ATE::TokenSqlError(_) | ATE::MalformedToken => {
+                "Błąd serwera. Skontaktuj się z webmasterem."
+            }
+            ATE::EmptyName => "Nazwa tokenu nie może być pusta.",
+            ATE::NoScopes => "Wybierz co najmniej jeden zakres uprawnień tokenu.",
+            ATE::NotFound => "Taki token nie istnieje.",
+        }
+    }
+}
+
+type TokenRow = (String, String, String, String, i64, Option<i64>);
+
+fn token_from_row(
+    (id, user_id, name, scopes, created_at, last_used): TokenRow,
+) -> Result<ApiToken, ApiTokenError> {
+    Ok(ApiToken {
+        id: Uuid::from_str(&id).map_err(|_| ApiTokenError::MalformedToken)?,
+        user_id: Uuid::from_str(&user_id).map_err(|_| ApiTokenError::MalformedToken)?,
+        name,
+        scopes: scopes
+            .split_whitespace()
+            .map(|s| Scope::from_str(s).map_err(|_| ApiTokenError::MalformedToken))
+            .collect::<Result<_, _>>()?,
+        created_at: DateTime::from_timestamp(created_at, 0).ok_or(ApiTokenError::MalformedToken)?,
+        last_used: last_used.and_then(|t| DateTime::from_timestamp(t, 0)),
+    })
+}
+
+impl ApiToken {
+    /// Creates a token for a user. Returns it along with the secret, which is only
+    /// ever shown this once; the database keeps just its hash.
+    pub fn create(
+        user_id: &Uuid,
+        name: &str,
+        scopes: Vec<Scope>,
+        conn: &Connection,
+    ) -> Result<(ApiToken, String), ApiTokenError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ApiTokenError::EmptyName);
+        }
+        if scopes.is_empty() {
+            return Err(ApiTokenError::NoScopes);
+        }
+        let secret = format!("{TOKEN_PREFIX}{}", generate_long_token());
+        let token = ApiToken {
+            id: Uuid::now_v7(),
+            user_id: *user_id,
+            name: name.to_owned(),
+            scopes,
+            created_at: Utc::now(),
+            last_used: None,
+        };
+        let scopes = token
+            .scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        conn.prepare(
+            "INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute((
+            token.id.to_string(),
+            user_id.to_string(),
+            &token.name,
+            hash_token(&secret),
+            scopes,
+            token.created_at.timestamp(),
+        ))?;
+        Ok((token, secret))
+    }
+    /// A user's tokens that haven't been revoked, newest first.
+    pub fn get_for_user(user_id: &Uuid, conn: &Connection) -> Result<Vec<ApiToken>, ApiTokenError> {
+        conn.prepare(
+            "SELECT id, user_id, name, scopes, created_at, last_used FROM api_tokens
+             WHERE user_id = ?1 AND revoked = 0 ORDER BY id DESC",
+        )?
+        .query_map([user_id.to_string()], |r| {
+            Ok((
+                r.get(0)?,
+                r.get(1)?,
+                r.get(2)?,
+                r.get(3)?,
+                r.get(4)?,
+                r.get(5)?,
+            ))
+        })?
+        .map(|r| token_from_row(r?))
+        .collect()
+    }
+    /// Looks up an active token by its secret, noting that it was just used.
+    pub fn authenticate(secret: &str, conn: &Connection) -> Result<ApiToken, ApiTokenError> {
+        let mut token = conn
+            .prepare(
+                "SELECT id, user_id, name, scopes, created_at, last_used FROM api_tokens
+                 WHERE token_hash = ?1 AND revoked = 0",
+            )?
+            .query_one([hash_token(secret)], |r| {
+                Ok((
+                    r.get(0)?,
+                    r.get(1)?,
+                    r.get(2)?,
+                    r.get(3)?,
+                    r.get(4)?,
+                    r.get(5)?,
+                ))
+            })
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => ApiTokenError::NotFound,
+                e => ApiTokenError::TokenSqlError(e),
+            })
+            .and_then(token_from_row)?;
+        let now = Utc::now();
+        if token.last_used.is_none_or(|l| now - l >= TOUCH_INTERVAL) {
+            conn.prepare("UPDATE api_tokens SET last_used = ?2 WHERE id = ?1")?
+                .execute((token.id.to_string(), now.timestamp()))?;
+            token.last_used = Some(now);
+        }
+        Ok(token)
+    }
+    /// Revokes one of a user's tokens.
+    pub fn revoke(id: &Uuid, user_id: &Uuid, conn: &Connection) -> Result<(), ApiTokenError> {
+        match conn
+            .prepare(
+                "UPDATE api_tokens SET revoked = 1 WHERE id = ?1 AND user_id = ?2 AND revoked = 0",
+            )?
+            .execute([id.to_string(), user_id.to_string()])?
+        {
+            0 => Err(ApiTokenError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
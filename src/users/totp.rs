@@ -0,0 +1,481 @@
+use std::{collections::HashSet, str::FromStr};
+
+use chrono::{Duration, Utc};
+use hmac::{
+    Hmac,
+    digest::{KeyInit, Mac},
+};
+use rusqlite::{Connection, OptionalExtension};
+use sha1::Sha1;
+use uuid::Uuid;
+
+use crate::{
+    crypto::{generate_long_token, generate_short_token, generate_totp_secret, hash_token},
+    users::{User, roles::Role},
+};
+
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from this many steps before or after the current one are accepted too,
+/// to allow for clock drift and slow typing.
+const ALLOWED_SKEW: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// How long after getting the password right the second factor may be entered.
+const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum TotpError {
+    #[error("Failed to execute SQL: {0}")]
+    TotpSqlError(#[from] rusqlite::Error),
+    #[error("Malformed TOTP secret found in DB")]
+    MalformedSecret,
+    #[error("Invalid or already used code")]
+    InvalidCode,
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error("Two-factor authentication is not enabled")]
+    NotEnabled,
+    #[error("No two-factor enrolment in progress")]
+    NoPendingEnrolment,
+    #[error("The user's role requires two-factor authentication")]
+    RequiredByRole,
+    #[error("Login challenge expired or does not exist")]
+    ChallengeExpired,
+}
+impl TotpError {
+    pub fn msg(&self) -> &'static str {
+        use TotpError as TE;
+        match self {
+            TE::TotpSqlError(_) | TE::MalformedSecret => {
+                "Błąd serwera. Skontaktuj się z webmasterem."
+            }
+            TE::InvalidCode => "Nieprawidłowy lub już wykorzystany kod.",
+            TE::AlreadyEnabled => "Weryfikacja dwuetapowa jest już włączona.",
+            TE::NotEnabled => "Weryfikacja dwuetapowa nie jest włączona.",
+            TE::NoPendingEnrolment => "Najpierw rozpocznij włączanie weryfikacji dwuetapowej.",
+            TE::RequiredByRole => "Twoja rola wymaga weryfikacji dwuetapowej.",
+            TE::ChallengeExpired => "Logowanie wygasło. Zaloguj się ponownie.",
+        }
+    }
+}
+
+/// How a second factor was proven.
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+impl SecondFactor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecondFactor::Totp => "totp",
+            SecondFactor::RecoveryCode => "recovery_code",
+        }
+    }
+}
+
+/// A user's two-factor setup, as shown on the settings page.
+pub struct TotpStatus {
+    pub enabled: bool,
+    /// Secret of an enrolment that still waits for its first code.
+    pub pending: Option<String>,
+    pub recovery_codes_left: usize,
+}
+
+/// RFC 4226 HOTP value for one counter value.
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = <Hmac<Sha1> as KeyInit>::new_from_slice(key).expect("HMAC takes any key");
+    Mac::update(&mut mac, &counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    value % 10u32.pow(DIGITS)
+}
+
+/// Finds the time step a code was generated for around the Unix time `now`, skipping
+/// steps up to and including `last_step` so that every code works only once.
+fn matching_step(
+    secret: &str,
+    code: &str,
+    last_step: Option<i64>,
+    now: i64,
+) -> Result<i64, TotpError> {
+    let key = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret)
+        .ok_or(TotpError::MalformedSecret)?;
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize {
+        return Err(TotpError::InvalidCode);
+    }
+    let code = code.parse::<u32>().map_err(|_| TotpError::InvalidCode)?;
+    let now = now / STEP_SECS;
+    (now - ALLOWED_SKEW..=now + ALLOWED_SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(&key, *step as u64) == code)
+        .ok_or(TotpError::InvalidCode)
+}
+
+/// `otpauth://` URI for enrolling a secret in an authenticator app, which shows the
+/// account as `handle` from `issuer`, the competition's name.
+pub fn otpauth_uri(secret: &str, handle: &str, issuer: &str) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+    format!(
+        "otpauth://totp/{issuer}:{handle}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+        issuer = encode(issuer),
+        handle = encode(handle),
+    )
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Replaces a user's recovery codes with fresh ones, returned in plain text to be
+/// shown once.
+fn replace_recovery_codes(user_id: &Uuid, conn: &Connection) -> Result<Vec<String>, TotpError> {
+    conn.prepare("DELETE FROM recovery_codes WHERE user_id = ?1")?
+        .execute([user_id.to_string()])?;
+    let codes = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_short_token())
+        .collect::<Vec<_>>();
+    let mut insert =
+        conn.prepare("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?1, ?2)")?;
+    for code in &codes {
+        insert.execute([
+            user_id.to_string(),
+            hash_token(&normalize_recovery_code(code)),
+        ])?;
+    }
+    Ok(codes)
+}
+
+fn secret(user_id: &Uuid, conn: &Connection) -> Result<Option<(String, Option<i64>)>, TotpError> {
+    Ok(conn
+        .prepare("SELECT totp_secret, totp_last_step FROM users WHERE id = ?1")?
+        .query_one([user_id.to_string()], |r| {
+            Ok(r.get::<_, Option<String>>(0)?
+                .map(|s| (s, r.get(1).ok().flatten())))
+        })
+        .optional()?
+        .flatten())
+}
+
+pub fn is_enabled(user_id: &Uuid, conn: &Connection) -> Result<bool, TotpError> {
+    Ok(secret(user_id, conn)?.is_some())
+}
+
+/// Ids of every user with two-factor authentication enabled.
+pub fn enabled_users(conn: &Connection) -> Result<HashSet<Uuid>, TotpError> {
+    conn.prepare("SELECT id FROM users WHERE totp_secret IS NOT NULL")?
+        .query_map([], |r| r.get::<_, String>(0))?
+        .map(|id| Uuid::from_str(&id?).map_err(|_| TotpError::MalformedSecret))
+        .collect()
+}
+
+pub fn status(user_id: &Uuid, conn: &Connection) -> Result<TotpStatus, TotpError> {
+    let (enabled, pending) = conn
+        .prepare("SELECT totp_secret IS NOT NULL, totp_pending FROM users WHERE id = ?1")?
+        .query_one([user_id.to_string()], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    let recovery_codes_left = conn
+        .prepare("SELECT COUNT(*) FROM recovery_codes WHERE user_id = ?1 AND used_at IS NULL")?
+        .query_one([user_id.to_string()], |r| r.get(0))?;
+    Ok(TotpStatus {
+        enabled,
+        pending,
+        recovery_codes_left,
+    })
+}
+
+/// Generates a new secret for the user to add to their authenticator app. It only
+/// takes effect once confirmed with a code from the app.
+pub fn begin_enrolment(user_id: &Uuid, conn: &Connection) -> Result<String, TotpError> {
+    if is_enabled(user_id, conn)? {
+        return Err(TotpError::AlreadyEnabled);
+    }
+    let secret = generate_totp_secret();
+    conn.prepare("UPDATE users SET totp_pending = ?2 WHERE id = ?1")?
+        .execute([user_id.to_string(), secret.clone()])?;
+    Ok(secret)
+}
+
+/// Enables the pending secret if `code` matches it. Returns the new recovery codes.
+pub fn confirm_enrolment(
+    user_id: &Uuid,
+    code: &str,
+    conn: &Connection,
+) -> Result<Vec<String>, TotpError> {
+    let status = status(user_id, conn)?;
+    if status.enabled {
+        return Err(TotpError::AlreadyEnabled);
+    }
+    let pending = status.pending.ok_or(TotpError::NoPendingEnrolment)?;
+    let step = matching_step(&pending, code, None, Utc::now().timestamp())?;
+    conn.prepare(
+        "UPDATE users SET totp_secret = totp_pending, totp_pending = NULL, totp_last_step = ?2
+         WHERE id = ?1",
+    )?
+    .execute((user_id.to_string(), step))?;
+    replace_recovery_codes(user_id, conn)
+}
+
+/// Checks a second factor, either a current TOTP code or an unused recovery code,
+/// and uses it up.
+pub fn check(user_id: &Uuid, code: &str, conn: &Connection) -> Result<SecondFactor, TotpError> {
+    let (secret, last_step) = secret(user_id, conn)?.ok_or(TotpError::NotEnabled)?;
+    match matching_step(&secret, code, last_step, Utc::now().timestamp()) {
+        Ok(step) => {
+            conn.prepare("UPDATE users SET totp_last_step = ?2 WHERE id = ?1")?
+                .execute((user_id.to_string(), step))?;
+            Ok(SecondFactor::Totp)
+        }
+        Err(TotpError::InvalidCode) => {
+            match conn
+                .prepare(
+                    "UPDATE recovery_codes SET used_at = ?3
+                     WHERE user_id = ?1 AND code_hash = ?2 AND used_at IS NULL",
+                )?
+                .execute((
+                    user_id.to_string(),
+                    hash_token(&normalize_recovery_code(code)),
+                    Utc::now().timestamp(),
+                ))? {
+                0 => Err(TotpError::InvalidCode),
+                _ => Ok(SecondFactor::RecoveryCode),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the user's recovery codes after checking a second factor.
+pub fn regenerate_recovery_codes(
+    user_id: &Uuid,
+    code: &str,
+    conn: &Connection,
+) -> Result<Vec<String>, TotpError> {
+    check(user_id, code, conn)?;
+    replace_recovery_codes(user_id, conn)
+}
+
+/// Turns two-factor authentication off, unless the user's role requires it.
+pub fn disable(user: &User, code: &str, conn: &Connection) -> Result<(), TotpError> {
+    if required_roles(conn)?.contains(&user.role) {
+        return Err(TotpError::RequiredByRole);
+    }
+    check(&user.id, code, conn)?;
+    reset(&user.id, conn)
+}
+
+/// Removes a user's second factor without asking for it, for when they've lost both
+/// their authenticator and their recovery codes.
+pub fn reset(user_id: &Uuid, conn: &Connection) -> Result<(), TotpError> {
+    if !is_enabled(user_id, conn)? {
+        return Err(TotpError::NotEnabled);
+    }
+    conn.prepare(
+        "UPDATE users SET totp_secret = NULL, totp_pending = NULL, totp_last_step = NULL
+         WHERE id = ?1",
+    )?
+    .execute([user_id.to_string()])?;
+    conn.prepare("DELETE FROM recovery_codes WHERE user_id = ?1")?
+        .execute([user_id.to_string()])?;
+    Ok(())
+}
+
+/// Roles whose accounts may only use the panel with two-factor authentication enabled.
+pub fn required_roles(conn: &Connection) -> Result<Vec<Role>, TotpError> {
+    let roles = conn
+        .prepare("SELECT totp_required_roles FROM config WHERE id_zero = 0")?
+        .query_one([], |r| r.get::<_, String>(0))?;
+    Ok(roles
+        .split_whitespace()
+        .filter_map(|r| Role::from_str(r).ok())
+        .collect())
+}
+
+pub fn set_required_roles(roles: &[Role], conn: &Connection) -> Result<(), TotpError> {
+    let roles = roles
+        .iter()
+        .map(|r| r.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    conn.prepare("UPDATE config SET totp_required_roles = ?1 WHERE id_zero = 0")?
+        .execute([roles])?;
+    Ok(())
+}
+
+/// Whether the user's role requires a second factor they haven't set up yet.
+pub fn is_missing(user: &User, conn: &Connection) -> Result<bool, TotpError> {
+    Ok(required_roles(conn)?.contains(&user.role) && !is_enabled(&user.id, conn)?)
+}
+
+/// Remembers that a user got their password right, returning a token to present along
+/// with the second factor.
+pub fn create_challenge(user_id: &Uuid, conn: &Connection) -> Result<String, TotpError> {
+    let token = generate_long_token();
+    conn.prepare("INSERT INTO login_challenges (token_hash, user_id, expiry) VALUES (?1, ?2, ?3)")?
+        .execute((
+            hash_token(&token),
+            user_id.to_string(),
+            (Utc::now() + CHALLENGE_LIFETIME).timestamp(),
+        ))?;
+    Ok(token)
+}
+
+/// The user a login challenge was issued for, while it hasn't expired.
+pub fn challenge_user(token: &str, conn: &Connection) -> Result<Uuid, TotpError> {
+    let user_id = conn
+        .prepare("SELECT user_id FROM login_challenges WHERE token_hash = ?1 AND expiry > ?2")?
+        .query_one((hash_token(token), Utc::now().timestamp()), |r| {
+            r.get::<_, String>(0)
+        })
+        .optional()?
+        .ok_or(TotpError::ChallengeExpired)?;
+    Uuid::from_str(&user_id).map_err(|_| TotpError::ChallengeExpired)
+}
+
+pub fn delete_challenge(token: &str, conn: &Connection) -> Result<(), TotpError> {
+    conn.prepare("DELETE FROM login_challenges WHERE token_hash = ?1")?
+        .execute([hash_token(token)])?;
+    Ok(())
+}
+
+pub fn delete_expired_challenges(conn: &Connection) -> Result<usize, TotpError> {
+    Ok(conn
+        .prepare("DELETE FROM login_challenges WHERE expiry < ?1")?
+        .execute([Utc::now().timestamp()])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    fn code_at(step: i64) -> String {
+        format!("{:06}", hotp(RFC_KEY, step as u64))
+    }
+
+    fn rfc_secret() -> String {
+        base32::encode(base32::Alphabet::Rfc4648 { padding: false }, RFC_KEY)
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_KEY, counter as u64), code, "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_vectors() {
+        // The RFC lists 8-digit SHA-1 codes; ours are their last 6 digits.
+        let expected = [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ];
+        for (time, code) in expected {
+            assert_eq!(hotp(RFC_KEY, (time / STEP_SECS) as u64), code, "T = {time}");
+            assert_eq!(
+                matching_step(&rfc_secret(), &format!("{code:06}"), None, time).unwrap(),
+                time / STEP_SECS
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_codes_one_step_off() {
+        let now = 1111111111;
+        let step = now / STEP_SECS;
+        for s in step - ALLOWED_SKEW..=step + ALLOWED_SKEW {
+            assert_eq!(
+                matching_step(&rfc_secret(), &code_at(s), None, now).unwrap(),
+                s
+            );
+        }
+        for s in [step - ALLOWED_SKEW - 1, step + ALLOWED_SKEW + 1] {
+            assert!(matches!(
+                matching_step(&rfc_secret(), &code_at(s), None, now),
+                Err(TotpError::InvalidCode)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_used_codes() {
+        let now = 1111111111;
+        let step = now / STEP_SECS;
+        let code = code_at(step);
+        assert!(matches!(
+            matching_step(&rfc_secret(), &code, Some(step), now),
+            Err(TotpError::InvalidCode)
+        ));
+        // An earlier code is no good once a later one has been used either.
+        assert!(matches!(
+            matching_step(&rfc_secret(), &code_at(step - 1), Some(step), now),
+            Err(TotpError::InvalidCode)
+        ));
+        assert_eq!(
+            matching_step(&rfc_secret(), &code_at(step + 1), Some(step), now).unwrap(),
+            step + 1
+        );
+    }
+
+    #[test]
+    fn uris_name_the_issuer_and_account() {
+        assert_eq!(
+            otpauth_uri("JBSWY3DPEHPK3PXP", "ala kot", "Zbiórka: WPiK"),
+            "otpauth://totp/Zbi%C3%B3rka%3A%20WPiK:ala%20kot?secret=JBSWY3DPEHPK3PXP\
+             &issuer=Zbi%C3%B3rka%3A%20WPiK&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let now = 1111111111;
+        let code = code_at(now / STEP_SECS);
+        assert_eq!(
+            matching_step(
+                &rfc_secret(),
+                &format!(" {} {} ", &code[..3], &code[3..]),
+                None,
+                now
+            )
+            .unwrap(),
+            now / STEP_SECS
+        );
+        for bad in ["", "12345", "1234567", "12a456"] {
+            assert!(matches!(
+                matching_step(&rfc_secret(), bad, None, now),
+                Err(TotpError::InvalidCode)
+            ));
+        }
+        assert!(matches!(
+            matching_step("not base32!", &code, None, now),
+            Err(TotpError::MalformedSecret)
+        ));
+    }
+}